@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Plain text portable pixmap (P3)
+    Ppm,
+    /// Portable Network Graphics, sRGB encoded
+    Png,
+    /// OpenEXR with linear radiance
+    Exr,
+    /// Radiance RGBE with linear radiance
+    Hdr,
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        // Infer the image format from the extension of the output file
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(OutputFormat::Ppm),
+            "png" => Some(OutputFormat::Png),
+            "exr" => Some(OutputFormat::Exr),
+            "hdr" => Some(OutputFormat::Hdr),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ExrSamples {
+    /// 16 bit floating point
+    Half,
+    /// 32 bit floating point
+    Float,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ToneMapName {
+    /// Cut off values above 1
+    Clamp,
+    /// Simple Reinhard, c / (1 + c)
+    Reinhard,
+    /// Reinhard on luminance with a white point, see --white-point
+    ReinhardExtended,
+    /// Fitted ACES filmic curve
+    Aces,
+    /// AgX
+    Agx,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LightSamplingName {
+    /// Every light equally often
+    Uniform,
+    /// In proportion to the power of each light
+    Power,
+    /// Through a light BVH, by estimated contribution to the shading point
+    Bvh,
+}
+
+
+/// Path trace a scene and write the image to disk.
+///
+/// Options given on the command line override the settings of the scene file.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    /// JSON scene description. Renders the final scene of the first book when omitted
+    pub scene: Option<PathBuf>,
+
+    /// Output image path
+    #[arg(short, long, default_value = "image.ppm")]
+    pub output: PathBuf,
+
+    /// Output image format, inferred from the output extension when omitted
+    #[arg(short, long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Bits per channel of PNG output
+    #[arg(long, default_value_t = 8, value_parser = parse_bit_depth)]
+    pub bit_depth: u8,
+
+    /// Sample precision of EXR output
+    #[arg(long, value_enum, default_value_t = ExrSamples::Float)]
+    pub exr_precision: ExrSamples,
+
+    /// Tone mapping operator for PPM and PNG output
+    #[arg(long, value_enum)]
+    pub tonemap: Option<ToneMapName>,
+
+    /// Exposure adjustment in stops, applied before tone mapping
+    #[arg(long, allow_negative_numbers = true)]
+    pub exposure: Option<f64>,
+
+    /// Luminance that maps to white with the extended Reinhard operator
+    #[arg(long, default_value_t = 4.0)]
+    pub white_point: f64,
+
+    /// Image width in pixels
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub width: Option<i32>,
+
+    /// Image height in pixels, replacing the aspect ratio of the scene
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub height: Option<i32>,
+
+    /// Samples per pixel
+    #[arg(short, long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub spp: Option<i32>,
+
+    /// Maximum number of ray bounces
+    #[arg(short = 'd', long, value_parser = clap::value_parser!(i32).range(0..))]
+    pub max_depth: Option<i32>,
+
+    /// Number of bounces before Russian roulette may end a path
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..))]
+    pub min_depth: Option<i32>,
+
+    /// How lights are picked for direct light sampling
+    #[arg(long, value_enum)]
+    pub light_sampling: Option<LightSamplingName>,
+
+    /// Number of render threads, all cores when omitted
+    #[arg(short = 'j', long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub threads: Option<u64>,
+
+    /// Seed for reproducible renders
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Don't report the render progress on stderr
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// Equirectangular environment map (.hdr, .exr, .png or .jpg) lighting the scene
+    #[arg(long)]
+    pub environment: Option<PathBuf>,
+
+    /// Rotation of the environment map around the vertical axis, in degrees
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, requires = "environment")]
+    pub environment_rotation: f64,
+
+    /// Brightness multiplier for the environment map
+    #[arg(long, default_value_t = 1.0, requires = "environment")]
+    pub environment_intensity: f64,
+
+    /// Also write depth, normal, albedo, position and object/material id buffers
+    #[arg(long)]
+    pub aovs: bool,
+
+    /// Write the AOVs as separate files even when the output is EXR
+    #[arg(long, requires = "aovs")]
+    pub separate_aovs: bool,
+}
+
+fn parse_bit_depth(value: &str) -> Result<u8, String> {
+    match value {
+        "8" => Ok(8),
+        "16" => Ok(16),
+        _ => Err("bit depth must be 8 or 16".to_string()),
+    }
+}
+
+impl Args {
+    pub fn output_format(&self) -> Result<OutputFormat, String> {
+        if let Some(format) = self.format {
+            return Ok(format);
+        }
+        OutputFormat::from_path(&self.output).ok_or_else(|| {
+            format!("cannot infer the image format of '{}', use --format", self.output.display())
+        })
+    }
+}
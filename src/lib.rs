@@ -0,0 +1,38 @@
+//! Path tracer following Peter Shirley's "Ray Tracing in One Weekend" series.
+//!
+//! Scenes are built from [`Hittable`] objects with [`Material`]s, collected in a
+//! [`HittableList`] and rendered through a [`Camera`]. Both traits can be implemented outside
+//! this crate to add new primitives and materials.
+
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+pub mod utility;
+
+pub use utility::aabb::Aabb;
+pub use utility::aov::{AovEncoding, AovKind, Aovs};
+pub use utility::background::{Background, EnvironmentMap};
+pub use utility::bvh::BvhNode;
+pub use utility::camera::Camera;
+pub use utility::colors::Color;
+pub use utility::framebuffer::Framebuffer;
+pub use utility::hittable::{HitRecord, Hittable};
+pub use utility::hittable_list::HittableList;
+pub use utility::interval::Interval;
+pub use utility::light_list::{LightList, LightSampling};
+pub use utility::linear_bvh::LinearBvh;
+pub use utility::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal, ScatterRecord, Scattered};
+pub use utility::onb::Onb;
+pub use utility::pdf::{CosinePdf, HittablePdf, Pdf};
+pub use utility::punctual_light::PunctualLight;
+pub use utility::quad::Quad;
+pub use utility::ray::Ray;
+pub use utility::scene::{load_scene, Scene};
+pub use utility::sky::PhysicalSky;
+pub use utility::sphere::Sphere;
+pub use utility::perlin::Perlin;
+pub use utility::texture::{
+    CheckerTexture, CloudTexture, ImageTexture, LinearImage, MarbleTexture, NoiseTexture, SolidColor, Texture, WoodTexture,
+    WrapMode,
+};
+pub use utility::triangle::{MeshData, MeshError, Triangle, TriangleMesh};
+pub use utility::vec3::{Point3, Vec3};
@@ -1,33 +1,40 @@
-mod utility;
-use utility::colors::Color;
-use utility::hittable_list::HittableList;
-use utility::vec3::{Point3,Vec3};
-use utility::sphere::Sphere;
-use utility::common;
-use utility::camera::Camera;
-use utility::material::{Dielectric, Lambertian, Metal};
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
 
+mod cli;
+
+use cli::{Args, ExrSamples, LightSamplingName, OutputFormat, ToneMapName};
+use ray_tracer_weekend_rust::utility::aov::{AovEncoding, AovKind};
+use ray_tracer_weekend_rust::utility::background::{Background, EnvironmentMap};
+use ray_tracer_weekend_rust::utility::common;
+use ray_tracer_weekend_rust::utility::framebuffer::Framebuffer;
+use ray_tracer_weekend_rust::utility::image_writer::{self, ExrChannel, ExrPrecision, PngBitDepth};
+use ray_tracer_weekend_rust::utility::scene;
+use ray_tracer_weekend_rust::utility::tonemap::{ToneMapOperator, ToneMapping};
+use ray_tracer_weekend_rust::{
+    Camera, Color, Dielectric, HittableList, Lambertian, LightList, LightSampling, LinearBvh, Metal, Point3, Sphere, Vec3,
+};
+
+use std::error::Error;
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::Arc;
 
-fn create_file(path: &str) -> File {
-    let fs = File::create(path)
-        .expect("image.ppm should be included in this project");
-    return fs;
-}
+use clap::Parser;
 
-fn main() -> std::io::Result<()> {
+fn book_one_final_scene() -> (Camera, HittableList) {
     // Constants
     const ASPECT_RATIO: f64 = 16.0 / 9.0;
     const IMAGE_WIDTH: i32 = 1200;
     const SAMPLES_PER_PIXEL: i32 = 500;
     const MAX_DEPTH: i32 = 50;
-    const VERTICAL_FIELD_OF_VIEW: f64 = 90.0;
-    let LOOK_FROM = Point3::new(0.0,0.0,0.0);
-    let LOOK_AT = Point3::new(0.0,0.0,-1.0);
-    let VUP = Vec3::new(0.0,1.0,0.0);
-    const DEFOCUS_ANGLE: f64 = 0.0;
+    const MIN_DEPTH: i32 = 5;
+    const VERTICAL_FIELD_OF_VIEW: f64 = 20.0;
+    let look_from = Point3::new(13.0, 2.0, 3.0);
+    let look_at = Point3::new(0.0, 0.0, 0.0);
+    let vup = Vec3::new(0.0, 1.0, 0.0);
+    const DEFOCUS_ANGLE: f64 = 0.6;
     const FOCUS_DIST: f64 = 10.0;
 
 
@@ -93,25 +100,187 @@ fn main() -> std::io::Result<()> {
     camera.aspect_ratio = ASPECT_RATIO;
     camera.samples_per_pixel = SAMPLES_PER_PIXEL;
     camera.max_depth = MAX_DEPTH;
+    camera.min_depth = MIN_DEPTH;
 
-    camera.vfov = 20.0; // VERTICAL_FIELD_OF_VIEW
-    camera.look_from = Point3::new(13.0, 2.0, 3.0); // LOOK_FROM;
-    camera.look_at = Point3::new(0.0, 0.0, 0.0); // LOOK_AT;
-    camera.vup = Vec3::new(0.0, 1.0, 0.0); // VUP;
+    camera.vfov = VERTICAL_FIELD_OF_VIEW;
+    camera.look_from = look_from;
+    camera.look_at = look_at;
+    camera.vup = vup;
 
-    camera.defocus_angle = 0.6; // DEFOCUS_ANGLE
+    camera.defocus_angle = DEFOCUS_ANGLE;
     camera.focus_dist = FOCUS_DIST;
 
+    return (camera, world);
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let format = args.output_format()?;
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build_global()?;
+    }
+    if let Some(seed) = args.seed {
+        common::seed_rng(seed);
+    }
+
+    let (mut camera, world, mut lights, mut tone_mapping) = match &args.scene {
+        Some(path) => {
+            let scene = scene::load_scene(path)?;
+            for warning in &scene.warnings {
+                eprintln!("warning: {}", warning);
+            }
+            (scene.camera, scene.world, scene.lights, scene.tone_mapping)
+        }
+        None => {
+            let (camera, world) = book_one_final_scene();
+            (camera, world, LightList::default(), ToneMapping::default())
+        }
+    };
+
+    // Command line settings take precedence over the scene
+    if let Some(width) = args.width {
+        camera.image_width = width;
+    }
+    if let Some(height) = args.height {
+        camera.aspect_ratio = camera.image_width as f64 / height as f64;
+    }
+    if let Some(spp) = args.spp {
+        camera.samples_per_pixel = spp;
+    }
+    if let Some(max_depth) = args.max_depth {
+        camera.max_depth = max_depth;
+    }
+    if let Some(min_depth) = args.min_depth {
+        camera.min_depth = min_depth;
+    }
+    if let Some(name) = args.light_sampling {
+        lights = lights.with_sampling(match name {
+            LightSamplingName::Uniform => LightSampling::Uniform,
+            LightSamplingName::Power => LightSampling::Power,
+            LightSamplingName::Bvh => LightSampling::Bvh,
+        });
+    }
+    camera.seed = args.seed;
+    if !args.quiet {
+        camera.progress = Some(Box::new(|remaining| {
+            if remaining > 0 {
+                eprint!("\rScanlines remaining: {} ", remaining);
+            } else {
+                eprintln!("\rDone.                          ");
+            }
+        }));
+    }
+    if let Some(path) = &args.environment {
+        let map = EnvironmentMap::load(path)?
+            .with_rotation(args.environment_rotation)
+            .with_intensity(args.environment_intensity);
+        camera.background = Background::Environment(map);
+    }
+    if let Some(exposure) = args.exposure {
+        tone_mapping.exposure = exposure;
+    }
+    if let Some(name) = args.tonemap {
+        tone_mapping.operator = match name {
+            ToneMapName::Clamp => ToneMapOperator::Clamp,
+            ToneMapName::Reinhard => ToneMapOperator::Reinhard,
+            ToneMapName::ReinhardExtended => ToneMapOperator::ReinhardExtended { white_point: args.white_point },
+            ToneMapName::Aces => ToneMapOperator::Aces,
+            ToneMapName::Agx => ToneMapOperator::AgX,
+        };
+    }
+
     camera.initialize();
 
-    // Make file
-    let mut output: File = create_file("image.ppm");
-    // Image is in .ppm format, first 2 lines are the header
-    writeln!(output, "P3")?;
-    writeln!(output, "{} {}\n255", camera.image_width, camera.image_height)?;
+    // Wrap the scene in a bounding volume hierarchy so each ray only tests nearby objects
+    let world = LinearBvh::new(world);
+
+    if !args.aovs {
+        let framebuffer = camera.render(&world, &lights);
+        return write_image(&framebuffer, &args.output, format, args, &tone_mapping);
+    }
+
+    let (framebuffer, aovs) = camera.render_with_aovs(&world, &lights);
+    if format == OutputFormat::Exr && !args.separate_aovs {
+        // Everything goes into one multi-layer EXR, with the beauty pass as the default layer
+        let mut channels = Vec::from(ExrChannel::rgb("", &framebuffer));
+        channels.extend(aovs.exr_channels());
+        let mut output = create_output(&args.output)?;
+        image_writer::write_exr_channels(framebuffer.width, framebuffer.height, channels, &mut output, exr_precision(args))?;
+        output.flush()?;
+        return Ok(());
+    }
 
-    camera.render(&mut world, &mut output); 
+    write_image(&framebuffer, &args.output, format, args, &tone_mapping)?;
+    // Display referred formats get a viewable version of each AOV, the others keep raw values
+    // as far as the format can store them
+    let encoding = match format {
+        OutputFormat::Ppm | OutputFormat::Png => AovEncoding::Display,
+        OutputFormat::Exr => AovEncoding::Raw,
+        OutputFormat::Hdr => AovEncoding::NonNegative,
+    };
+    for kind in AovKind::ALL {
+        let path = aov_path(&args.output, kind);
+        write_image(&aovs.framebuffer(kind, encoding), &path, format, args, &ToneMapping::default())?;
+    }
 
     Ok(())
 }
 
+fn create_output(path: &Path) -> Result<BufWriter<File>, Box<dyn Error>> {
+    let file = File::create(path)
+        .map_err(|e| format!("cannot create '{}': {}", path.display(), e))?;
+    Ok(BufWriter::new(file))
+}
+
+fn exr_precision(args: &Args) -> ExrPrecision {
+    match args.exr_precision {
+        ExrSamples::Half => ExrPrecision::Half,
+        ExrSamples::Float => ExrPrecision::Float,
+    }
+}
+
+fn aov_path(output: &Path, kind: AovKind) -> PathBuf {
+    // image.png becomes image.normal.png and so on
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}.{}", stem, kind.name());
+    if let Some(extension) = output.extension() {
+        name = format!("{}.{}", name, extension.to_string_lossy());
+    }
+    output.with_file_name(name)
+}
+
+fn write_image(
+    framebuffer: &Framebuffer,
+    path: &Path,
+    format: OutputFormat,
+    args: &Args,
+    tone_mapping: &ToneMapping,
+) -> Result<(), Box<dyn Error>> {
+    let mut output = create_output(path)?;
+    match format {
+        // Tone mapping only applies to the display referred formats, EXR and HDR keep radiance
+        OutputFormat::Ppm => image_writer::write_ppm(framebuffer, &mut output, tone_mapping)?,
+        OutputFormat::Png => {
+            let bit_depth = if args.bit_depth == 16 { PngBitDepth::Sixteen } else { PngBitDepth::Eight };
+            image_writer::write_png(framebuffer, &mut output, bit_depth, tone_mapping)?;
+        }
+        OutputFormat::Exr => image_writer::write_exr(framebuffer, &mut output, exr_precision(args))?,
+        OutputFormat::Hdr => image_writer::write_hdr(framebuffer, &mut output)?,
+    }
+    output.flush()?;
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -8,3 +8,25 @@ pub mod common;
 pub mod interval;
 pub mod camera;
 pub mod material;
+pub mod aabb;
+pub mod bvh;
+pub mod linear_bvh;
+pub mod triangle;
+pub mod obj;
+pub mod ply;
+pub mod gltf_import;
+pub mod scene;
+pub mod framebuffer;
+pub mod image_writer;
+pub mod tonemap;
+pub mod aov;
+pub mod texture;
+pub mod perlin;
+pub mod quad;
+pub mod background;
+pub mod distribution;
+pub mod sky;
+pub mod onb;
+pub mod pdf;
+pub mod light_list;
+pub mod punctual_light;
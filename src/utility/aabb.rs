@@ -0,0 +1,196 @@
+use crate::utility::interval::Interval;
+use crate::utility::ray::Ray;
+use crate::utility::vec3::{Point3, Vec3};
+
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb { x: Interval::EMPTY, y: Interval::EMPTY, z: Interval::EMPTY };
+    pub const UNIVERSE: Aabb = Aabb { x: Interval::UNIVERSE, y: Interval::UNIVERSE, z: Interval::UNIVERSE };
+
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
+        Aabb { x: x, y: y, z: z }
+    }
+
+    pub fn from_points(a: Point3, b: Point3) -> Self {
+        // Treat the two points a and b as extrema for the bounding box, so we don't require a
+        // particular minimum/maximum coordinate order
+        Aabb {
+            x: if a.x <= b.x { Interval::new(a.x, b.x) } else { Interval::new(b.x, a.x) },
+            y: if a.y <= b.y { Interval::new(a.y, b.y) } else { Interval::new(b.y, a.y) },
+            z: if a.z <= b.z { Interval::new(a.z, b.z) } else { Interval::new(b.z, a.z) },
+        }
+    }
+
+    pub fn pad_to_minimums(&self) -> Self {
+        // Adjust the box so that no side is narrower than some delta, padding if necessary.
+        // Flat primitives such as axis-aligned triangles would otherwise never be hit
+        let delta = 0.0001;
+        let pad = |ax: &Interval| if ax.size() < delta { ax.expand(delta) } else { *ax };
+        Aabb {
+            x: pad(&self.x),
+            y: pad(&self.y),
+            z: pad(&self.z),
+        }
+    }
+
+    pub fn surrounding(box0: &Aabb, box1: &Aabb) -> Self {
+        Aabb {
+            x: Interval::enclosing(&box0.x, &box1.x),
+            y: Interval::enclosing(&box0.y, &box1.y),
+            z: Interval::enclosing(&box0.z, &box1.z),
+        }
+    }
+
+    pub fn axis_interval(&self, n: usize) -> &Interval {
+        match n {
+            1 => &self.y,
+            2 => &self.z,
+            _ => &self.x,
+        }
+    }
+
+    pub fn hit(&self, r: &Ray, mut ray_t: Interval) -> bool {
+        let ray_orig = r.origin();
+        let ray_dir = r.direction();
+
+        for axis in 0..3 {
+            let ax = self.axis_interval(axis);
+            let (orig, dir) = match axis {
+                0 => (ray_orig.x, ray_dir.x),
+                1 => (ray_orig.y, ray_dir.y),
+                _ => (ray_orig.z, ray_dir.z),
+            };
+            let adinv = 1.0 / dir;
+
+            let t0 = (ax.min - orig) * adinv;
+            let t1 = (ax.max - orig) * adinv;
+
+            if t0 < t1 {
+                if t0 > ray_t.min { ray_t.min = t0; }
+                if t1 < ray_t.max { ray_t.max = t1; }
+            } else {
+                if t1 > ray_t.min { ray_t.min = t1; }
+                if t0 < ray_t.max { ray_t.max = t0; }
+            }
+
+            // A ray that only touches the box still counts as a hit. Boxes around objects
+            // smaller than the spacing of doubles along the ray collapse to a single t, and
+            // rejecting them would skip hits the objects themselves report
+            if ray_t.max < ray_t.min {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    pub fn hit_precomputed(&self, origin: &Point3, inv_dir: &Vec3, mut ray_t: Interval) -> bool {
+        // Same slab test as hit(), for callers that test many boxes against one ray and can
+        // compute the reciprocal ray direction once up front
+        let slabs = [
+            (&self.x, origin.x, inv_dir.x),
+            (&self.y, origin.y, inv_dir.y),
+            (&self.z, origin.z, inv_dir.z),
+        ];
+
+        for (ax, orig, adinv) in slabs {
+            let t0 = (ax.min - orig) * adinv;
+            let t1 = (ax.max - orig) * adinv;
+            let (t_near, t_far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            if t_near > ray_t.min { ray_t.min = t_near; }
+            if t_far < ray_t.max { ray_t.max = t_far; }
+
+            if ray_t.max < ray_t.min {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        return Point3::new(
+            0.5 * (self.x.min + self.x.max),
+            0.5 * (self.y.min + self.y.max),
+            0.5 * (self.z.min + self.z.max),
+        );
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        // An empty box has no area, rather than the negative infinite size of its intervals
+        let dx = f64::max(0.0, self.x.size());
+        let dy = f64::max(0.0, self.y.size());
+        let dz = f64::max(0.0, self.z.size());
+        return 2.0 * (dx*dy + dy*dz + dz*dx);
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        // Returns the index of the longest axis of the bounding box
+        if self.x.size() > self.y.size() {
+            if self.x.size() > self.z.size() { 0 } else { 2 }
+        } else if self.y.size() > self.z.size() {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::common;
+    use crate::utility::vec3;
+
+    fn both(bbox: &Aabb, r: &Ray, ray_t: Interval) -> (bool, bool) {
+        let d = r.direction();
+        let inv_dir = Vec3::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+        return (bbox.hit(r, ray_t), bbox.hit_precomputed(&r.origin(), &inv_dir, ray_t));
+    }
+
+    #[test]
+    fn precomputed_slab_test_agrees_with_hit() {
+        common::seed_rng(2);
+        let bbox = Aabb::from_points(Point3::new(-1.0, -0.5, 0.0), Point3::new(1.0, 0.5, 2.0));
+        let mut hits = 0;
+        for _ in 0..10000 {
+            // Aim near the box, so that plenty of rays both hit and miss it
+            let origin = Vec3::random_range(-3.0, 3.0);
+            let target = Vec3::random_range(-1.5, 2.0);
+            let r = Ray::new(origin, vec3::unit_vector(target - origin));
+            let ray_t = Interval::new(0.001, common::random_double_range(0.0, 6.0));
+            let (hit, precomputed) = both(&bbox, &r, ray_t);
+            assert_eq!(hit, precomputed, "{:?} {:?} {:?}", r.origin(), r.direction(), ray_t);
+            hits += hit as usize;
+        }
+        assert!(hits > 2000 && hits < 8000, "{}", hits);
+    }
+
+    #[test]
+    fn touching_a_box_is_a_hit() {
+        let bbox = Aabb::from_points(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+
+        // The interval ends exactly where the ray enters the box
+        let r = Ray::new(Point3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(both(&bbox, &r, Interval::new(0.0, 1.0)), (true, true));
+        assert_eq!(both(&bbox, &r, Interval::new(0.0, 0.999)), (false, false));
+
+        // The ray passes through an edge of the box and nowhere else
+        let r = Ray::new(Point3::new(-1.0, 0.0, 0.5), Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(both(&bbox, &r, Interval::UNIVERSE), (true, true));
+        let r = Ray::new(Point3::new(-1.0, 0.001, 0.5), Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(both(&bbox, &r, Interval::UNIVERSE), (false, false));
+
+        // A box with no thickness along the ray
+        let flat = Aabb::from_points(Point3::new(1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(both(&flat, &r, Interval::new(0.001, f64::INFINITY)), (true, true));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::utility::colors::Color;
+use crate::utility::framebuffer::Framebuffer;
+use crate::utility::image_writer::ExrChannel;
+use crate::utility::vec3::Vec3;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AovKind {
+    Depth,
+    Normal,
+    Albedo,
+    Position,
+    ObjectId,
+    MaterialId,
+}
+
+impl AovKind {
+    pub const ALL: [AovKind; 6] = [
+        AovKind::Depth,
+        AovKind::Normal,
+        AovKind::Albedo,
+        AovKind::Position,
+        AovKind::ObjectId,
+        AovKind::MaterialId,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AovKind::Depth => "depth",
+            AovKind::Normal => "normal",
+            AovKind::Albedo => "albedo",
+            AovKind::Position => "position",
+            AovKind::ObjectId => "object_id",
+            AovKind::MaterialId => "material_id",
+        }
+    }
+}
+
+
+// How the values of an AOV are written out, chosen by what the output format can hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AovEncoding {
+    Display, // Remapped into [0,1] for viewing
+    Raw, // Unchanged, with infinite depth and id -1 where nothing was hit
+    NonNegative, // For RGBE: misses get depth 0 and ids are stored plus one, so 0 means no id
+}
+
+
+// Auxiliary buffers describing the first surface seen through each pixel. Normal, albedo and
+// position are averaged over the pixel samples that hit something, and are zero where nothing
+// was hit. Depth and the ids belong to the nearest hit among the samples
+pub struct Aovs {
+    pub width: usize,
+    pub height: usize,
+    pub depth: Vec<f64>, // Distance from the camera, infinite where nothing was hit
+    pub normal: Framebuffer,
+    pub albedo: Framebuffer,
+    pub position: Framebuffer,
+    pub object_id: Vec<Option<usize>>,
+    pub material_id: Vec<Option<usize>>,
+}
+
+impl Aovs {
+    pub fn new(width: usize, height: usize) -> Self {
+        Aovs {
+            width: width,
+            height: height,
+            depth: vec![f64::INFINITY; width * height],
+            normal: Framebuffer::new(width, height),
+            albedo: Framebuffer::new(width, height),
+            position: Framebuffer::new(width, height),
+            object_id: vec![None; width * height],
+            material_id: vec![None; width * height],
+        }
+    }
+
+    pub fn exr_channels(&self) -> Vec<ExrChannel> {
+        // Every buffer as channels of its own layer, with -1 marking pixels without an id
+        let id_samples = |ids: &Vec<Option<usize>>| ids.iter().map(|id| id.map_or(-1.0, |id| id as f32)).collect();
+        let xyz = |prefix: &str, framebuffer: &Framebuffer| {
+            let [x, y, z] = ExrChannel::rgb(prefix, framebuffer);
+            [
+                ExrChannel::new(format!("{}.X", prefix), x.samples),
+                ExrChannel::new(format!("{}.Y", prefix), y.samples),
+                ExrChannel::new(format!("{}.Z", prefix), z.samples),
+            ]
+        };
+
+        let mut channels = vec![ExrChannel::new("depth.Z", self.depth.iter().map(|&d| d as f32).collect())];
+        channels.extend(xyz("normal", &self.normal));
+        channels.extend(ExrChannel::rgb("albedo", &self.albedo));
+        channels.extend(xyz("position", &self.position));
+        channels.push(ExrChannel::new("object_id.id", id_samples(&self.object_id)));
+        channels.push(ExrChannel::new("material_id.id", id_samples(&self.material_id)));
+        return channels;
+    }
+
+    pub fn framebuffer(&self, kind: AovKind, encoding: AovEncoding) -> Framebuffer {
+        // Copy one buffer into a color framebuffer, with scalars replicated to all three
+        // channels. For display, depth is normalized by the farthest hit, normals are mapped
+        // from [-1,1] to [0,1] and ids get distinct colors
+        let mut framebuffer = Framebuffer::new(self.width, self.height);
+        let id_color = |id: Option<usize>| match (encoding, id) {
+            (AovEncoding::Display, Some(id)) => hash_color(id),
+            (AovEncoding::Display, None) => Color::from_float(0.0),
+            (AovEncoding::Raw, Some(id)) => Color::from_float(id as f64),
+            (AovEncoding::Raw, None) => Color::from_float(-1.0),
+            (AovEncoding::NonNegative, id) => Color::from_float(id.map_or(0.0, |id| id as f64 + 1.0)),
+        };
+
+        match kind {
+            AovKind::Depth => {
+                let max_depth = self.depth.iter().cloned().filter(|d| d.is_finite()).fold(0.0, f64::max);
+                for (pixel, &depth) in framebuffer.pixels.iter_mut().zip(&self.depth) {
+                    *pixel = match (encoding, depth.is_finite()) {
+                        (AovEncoding::Display, true) if max_depth > 0.0 => Color::from_float(depth / max_depth),
+                        (AovEncoding::Raw, _) | (AovEncoding::NonNegative, true) => Color::from_float(depth),
+                        _ => Color::from_float(0.0),
+                    };
+                }
+            }
+            AovKind::Normal if encoding == AovEncoding::Display => {
+                for (pixel, &n) in framebuffer.pixels.iter_mut().zip(&self.normal.pixels) {
+                    *pixel = if n.near_zero() { n } else { 0.5 * (n + Vec3::from_float(1.0)) };
+                }
+            }
+            AovKind::Normal => framebuffer.pixels.clone_from(&self.normal.pixels),
+            AovKind::Albedo => framebuffer.pixels.clone_from(&self.albedo.pixels),
+            AovKind::Position => framebuffer.pixels.clone_from(&self.position.pixels),
+            AovKind::ObjectId => {
+                for (pixel, &id) in framebuffer.pixels.iter_mut().zip(&self.object_id) {
+                    *pixel = id_color(id);
+                }
+            }
+            AovKind::MaterialId => {
+                for (pixel, &id) in framebuffer.pixels.iter_mut().zip(&self.material_id) {
+                    *pixel = id_color(id);
+                }
+            }
+        }
+        return framebuffer;
+    }
+}
+
+
+fn hash_color(id: usize) -> Color {
+    // Spread consecutive ids over clearly different colors
+    let mut h = (id as u64).wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    h ^= h >> 29;
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f64 / 255.0;
+    return Color::new(channel(0), channel(8), channel(16));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::camera::Camera;
+    use crate::utility::hittable_list::HittableList;
+    use crate::utility::image_writer;
+    use crate::utility::light_list::LightList;
+    use crate::utility::material::Lambertian;
+    use crate::utility::sphere::Sphere;
+    use image::ImageFormat;
+    use std::sync::Arc;
+
+    fn render_sphere_against_background() -> Aovs {
+        // A sphere filling the middle of the image, with the background showing in the corners
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Arc::new(Lambertian::new(Color::new(0.5, 0.25, 0.125))))));
+        let mut camera = Camera::new();
+        camera.aspect_ratio = 1.0;
+        camera.image_width = 9;
+        camera.samples_per_pixel = 4;
+        camera.max_depth = 2;
+        camera.vfov = 60.0;
+        camera.look_from = Vec3::new(0.0, 0.0, 0.0);
+        camera.look_at = Vec3::new(0.0, 0.0, -1.0);
+        camera.vup = Vec3::new(0.0, 1.0, 0.0);
+        camera.focus_dist = 1.0;
+        camera.seed = Some(3);
+        camera.initialize();
+        return camera.render_with_aovs(&world, &LightList::new(Vec::new())).1;
+    }
+
+    #[test]
+    fn background_pixels_have_no_hit() {
+        let aovs = render_sphere_against_background();
+        let (center, corner) = (4 * 9 + 4, 0);
+        assert!((aovs.depth[center] - 2.0).abs() < 0.05, "{}", aovs.depth[center]);
+        assert_eq!(aovs.object_id[center], Some(0));
+        assert_eq!(aovs.material_id[center], Some(0));
+        assert!((aovs.albedo.pixels[center].x - 0.5).abs() < 1e-9);
+
+        assert_eq!(aovs.depth[corner], f64::INFINITY);
+        assert_eq!(aovs.object_id[corner], None);
+        assert_eq!(aovs.material_id[corner], None);
+        assert!(aovs.normal.pixels[corner].near_zero());
+
+        let raw = aovs.framebuffer(AovKind::ObjectId, AovEncoding::Raw);
+        assert_eq!((raw.pixels[center].x, raw.pixels[corner].x), (0.0, -1.0));
+        let raw = aovs.framebuffer(AovKind::Depth, AovEncoding::Raw);
+        assert_eq!(raw.pixels[corner].x, f64::INFINITY);
+    }
+
+    #[test]
+    fn every_encoding_of_background_pixels_can_be_written_as_hdr() {
+        let aovs = render_sphere_against_background();
+        for kind in [AovKind::Depth, AovKind::ObjectId, AovKind::MaterialId] {
+            for encoding in [AovEncoding::Display, AovEncoding::NonNegative] {
+                let framebuffer = aovs.framebuffer(kind, encoding);
+                assert!(framebuffer.pixels.iter().all(|p| p.x.is_finite() && p.x >= 0.0), "{:?} {:?}", kind, encoding);
+            }
+        }
+
+        // Ids are shifted so that 0 is left for the background, and misses are at depth 0
+        let ids = aovs.framebuffer(AovKind::ObjectId, AovEncoding::NonNegative);
+        let depth = aovs.framebuffer(AovKind::Depth, AovEncoding::NonNegative);
+        let mut bytes = Vec::new();
+        image_writer::write_hdr(&ids, &mut bytes).unwrap();
+        let decoded = image::load_from_memory_with_format(&bytes, ImageFormat::Hdr).unwrap().into_rgb32f();
+        assert_eq!((decoded.get_pixel(4, 4).0[0], decoded.get_pixel(0, 0).0[0]), (1.0, 0.0));
+        assert_eq!(depth.pixels[0].x, 0.0);
+    }
+}
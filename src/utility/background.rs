@@ -0,0 +1,230 @@
+use std::path::Path;
+
+use crate::utility::colors::{self, Color};
+use crate::utility::common;
+use crate::utility::distribution::Distribution2D;
+use crate::utility::sky::PhysicalSky;
+use crate::utility::texture::{LinearImage, TextureError, WrapMode};
+use crate::utility::vec3::{self, Vec3};
+
+
+// Radiance arriving from directions where a ray leaves the scene. Since rays that escape pick
+// it up like any other emission, a bright background also lights the scene
+pub enum Background {
+    Solid(Color),
+    Gradient { bottom: Color, top: Color }, // Blended by the height of the ray direction
+    Environment(EnvironmentMap),
+    Sky(PhysicalSky),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        // The white to blue sky of the first book
+        Background::Gradient { bottom: Color::new(1.0, 1.0, 1.0), top: Color::new(0.5, 0.7, 1.0) }
+    }
+}
+
+impl Background {
+    pub fn value(&self, direction: Vec3) -> Color {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let unit_direction = vec3::unit_vector(direction);
+                let a = 0.5 * (unit_direction.y + 1.0);
+                (1.0 - a) * *bottom + a * *top
+            }
+            Background::Environment(map) => map.value(direction),
+            Background::Sky(sky) => sky.value(direction),
+        }
+    }
+
+    pub fn sample(&self) -> Option<(Vec3, f64)> {
+        // Pick a direction towards the bright parts of the background, with its solid angle
+        // density. Plain colors and gradients are left to scattered rays
+        match self {
+            Background::Solid(_) | Background::Gradient { .. } => None,
+            Background::Environment(map) => map.sample(),
+            Background::Sky(sky) => sky.sample(),
+        }
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        match self {
+            Background::Solid(_) | Background::Gradient { .. } => 0.0,
+            Background::Environment(map) => map.pdf(direction),
+            Background::Sky(sky) => sky.pdf(direction),
+        }
+    }
+}
+
+
+// An equirectangular (latitude-longitude) image of the surroundings. The image center looks
+// down -z, the default camera direction, and the top row is straight up
+pub struct EnvironmentMap {
+    image: LinearImage, // Radiance before the intensity scale
+    rotation: f64, // Turn around the vertical axis, in radians
+    intensity: f64, // Scale applied to the stored radiance
+    distribution: Distribution2D, // For picking directions in proportion to their brightness
+}
+
+impl EnvironmentMap {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TextureError> {
+        // Radiance HDR and OpenEXR files give the radiance directly. PNG and JPEG files also
+        // work, and are decoded from sRGB
+        return Ok(EnvironmentMap::from_image(LinearImage::load(path)?));
+    }
+
+    pub fn from_texels(width: usize, height: usize, texels: Vec<Color>) -> Self {
+        EnvironmentMap::from_image(LinearImage::from_texels(width, height, texels))
+    }
+
+    pub fn from_image(image: LinearImage) -> Self {
+        // Weight each texel by the solid angle it covers, which shrinks towards the poles
+        let (width, height) = (image.width(), image.height());
+        let func: Vec<f64> = image
+            .texels()
+            .iter()
+            .enumerate()
+            .map(|(index, &texel)| {
+                let theta = common::PI * ((index / width) as f64 + 0.5) / height as f64;
+                colors::luminance(texel).max(0.0) * theta.sin()
+            })
+            .collect();
+        let distribution = Distribution2D::new(&func, width, height);
+
+        EnvironmentMap {
+            image: image,
+            rotation: 0.0,
+            intensity: 1.0,
+            distribution: distribution,
+        }
+    }
+
+    pub fn with_rotation(mut self, degrees: f64) -> Self {
+        self.rotation = common::degrees_to_radians(degrees);
+        return self;
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        return self;
+    }
+
+    pub fn value(&self, direction: Vec3) -> Color {
+        // Bilinear lookup, wrapping around horizontally and clamped at the poles
+        let (u, v) = self.direction_to_uv(direction);
+        return self.intensity * self.image.bilinear(u, v, WrapMode::Repeat, WrapMode::Clamp);
+    }
+
+    pub fn sample(&self) -> Option<(Vec3, f64)> {
+        // Pick a direction with probability roughly proportional to the radiance coming from
+        // it. Returns the direction and its density with respect to solid angle
+        let ((u, v), pdf_uv) = self.distribution.sample(common::random_double(), common::random_double());
+        let theta = v * common::PI;
+        let sin_theta = theta.sin();
+        if pdf_uv == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+
+        // Change of variables from the unit square to the sphere of directions
+        let pdf = pdf_uv / (2.0 * common::PI * common::PI * sin_theta);
+        return Some((self.uv_to_direction(u, v), pdf));
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        // Solid angle density of sample() producing the given direction
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = f64::sin(v * common::PI);
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        return self.distribution.pdf(u, v) / (2.0 * common::PI * common::PI * sin_theta);
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        // Inverse of direction_to_uv
+        let phi = (u - 0.5) * 2.0 * common::PI + self.rotation;
+        let theta = v * common::PI;
+        return Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+    }
+
+    fn direction_to_uv(&self, direction: Vec3) -> (f64, f64) {
+        // u grows towards the right of a viewer looking down -z, v from the top down
+        let d = vec3::unit_vector(direction);
+        let phi = f64::atan2(d.x, -d.z) - self.rotation;
+        let theta = f64::acos(f64::clamp(d.y, -1.0, 1.0));
+        let u = (phi / (2.0 * common::PI) + 0.5).rem_euclid(1.0);
+        return (u, theta / common::PI);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_map() -> EnvironmentMap {
+        // A dim overcast sky with one bright texel standing in for the sun
+        let (width, height) = (16, 8);
+        let mut texels = vec![Color::new(0.2, 0.3, 0.4); width * height];
+        texels[2 * width + 5] = Color::new(200.0, 180.0, 150.0);
+        return EnvironmentMap::from_texels(width, height, texels).with_rotation(30.0);
+    }
+
+    fn integrate_over_sphere(f: impl Fn(Vec3) -> f64) -> f64 {
+        // Midpoint rule over the azimuth and the cosine of the polar angle, which is uniform in
+        // solid angle
+        let n = 1024;
+        let mut sum = 0.0;
+        for j in 0..n {
+            let cos_theta = 1.0 - 2.0 * (j as f64 + 0.5) / n as f64;
+            let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
+            for i in 0..n {
+                let phi = 2.0 * common::PI * (i as f64 + 0.5) / n as f64;
+                sum += f(Vec3::new(sin_theta * phi.sin(), cos_theta, -sin_theta * phi.cos()));
+            }
+        }
+        return sum * 4.0 * common::PI / (n * n) as f64;
+    }
+
+    #[test]
+    fn sampled_directions_carry_the_density_of_pdf() {
+        common::seed_rng(7);
+        let map = test_map();
+        for _ in 0..1000 {
+            let (direction, pdf) = map.sample().expect("the map has radiance");
+            let expected = map.pdf(direction);
+            assert!((pdf - expected).abs() <= 1e-6 * expected, "{} vs {}", pdf, expected);
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_over_the_sphere() {
+        let map = test_map();
+        let integral = integrate_over_sphere(|direction| map.pdf(direction));
+        assert!((integral - 1.0).abs() < 1e-2, "{}", integral);
+    }
+
+    #[test]
+    fn mis_with_uniform_sampling_is_unbiased() {
+        // One sample from the map and one uniform over the sphere, weighted with the power
+        // heuristic as the camera does, estimate the luminance integrated over the sphere
+        common::seed_rng(11);
+        let map = test_map();
+        let luminance = |direction: Vec3| colors::luminance(map.value(direction));
+        let weight = |pdf: f64, other: f64| pdf * pdf / (pdf * pdf + other * other);
+        let uniform_pdf = 1.0 / (4.0 * common::PI);
+
+        let samples = 20000;
+        let mut estimate = 0.0;
+        for _ in 0..samples {
+            let (direction, pdf) = map.sample().expect("the map has radiance");
+            estimate += weight(pdf, uniform_pdf) * luminance(direction) / pdf;
+            let direction = vec3::random_unit_vector();
+            estimate += weight(uniform_pdf, map.pdf(direction)) * luminance(direction) / uniform_pdf;
+        }
+        estimate /= samples as f64;
+
+        let expected = integrate_over_sphere(luminance);
+        assert!((estimate - expected).abs() < 0.02 * expected, "{} vs {}", estimate, expected);
+    }
+}
@@ -0,0 +1,116 @@
+use std::cmp::Ordering;
+
+use crate::utility::aabb::Aabb;
+use crate::utility::hittable::{HitRecord, Hittable};
+use crate::utility::hittable_list::HittableList;
+use crate::utility::interval::Interval;
+use crate::utility::ray::Ray;
+
+
+pub struct BvhNode {
+    left: Box<dyn Hittable>,
+    right: Option<Box<dyn Hittable>>,
+    bbox: Aabb,
+}
+
+
+impl BvhNode {
+    pub fn new(list: HittableList) -> Self {
+        // Build the hierarchy from every object in the list. The list is consumed, since the
+        // tree takes ownership of its objects
+        Self::from_objects(list.objects)
+    }
+
+    pub fn from_objects(mut objects: Vec<Box<dyn Hittable>>) -> Self {
+        // Build the bounding box of the span of source objects
+        let bbox = objects
+            .iter()
+            .fold(Aabb::EMPTY, |bbox, object| Aabb::surrounding(&bbox, &object.bounding_box()));
+
+        match objects.len() {
+            // An empty scene gets a node with an empty box around an empty list, which is never hit
+            0 => BvhNode {
+                left: Box::new(HittableList::new()),
+                right: None,
+                bbox: bbox,
+            },
+            1 => BvhNode {
+                left: objects.pop().unwrap(),
+                right: None,
+                bbox: bbox,
+            },
+            2 => {
+                let right = objects.pop().unwrap();
+                let left = objects.pop().unwrap();
+                BvhNode {
+                    left: left,
+                    right: Some(right),
+                    bbox: bbox,
+                }
+            }
+            _ => {
+                // Split along the longest axis of the span at the median object
+                let axis = bbox.longest_axis();
+                objects.sort_by(|a, b| Self::box_compare(a.as_ref(), b.as_ref(), axis));
+
+                let mid = objects.len() / 2;
+                let right_objects = objects.split_off(mid);
+                BvhNode {
+                    left: Box::new(Self::from_objects(objects)),
+                    right: Some(Box::new(Self::from_objects(right_objects))),
+                    bbox: bbox,
+                }
+            }
+        }
+    }
+
+    fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis: usize) -> Ordering {
+        let a_axis_interval = *a.bounding_box().axis_interval(axis);
+        let b_axis_interval = *b.bounding_box().axis_interval(axis);
+        a_axis_interval.min.total_cmp(&b_axis_interval.min)
+    }
+}
+
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        if !self.bbox.hit(r, ray_t) {
+            return None;
+        }
+
+        let hit_left = self.left.hit(r, ray_t);
+        let right_max = match &hit_left {
+            Some(rec) => rec.t,
+            None => ray_t.max,
+        };
+        let hit_right = match &self.right {
+            Some(right) => right.hit(r, Interval::new(ray_t.min, right_max)),
+            None => None,
+        };
+
+        return hit_right.or(hit_left);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::vec3::{Point3, Vec3};
+
+    #[test]
+    fn empty_list_builds_a_node_that_is_never_hit() {
+        let bvh = BvhNode::new(HittableList::new());
+        let bbox = bvh.bounding_box();
+        assert!(bbox.x.min > bbox.x.max && bbox.y.min > bbox.y.max && bbox.z.min > bbox.z.max);
+
+        for direction in [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.3, 0.4, -0.5)] {
+            let r = Ray::new(Point3::new(0.0, 0.0, 0.0), direction);
+            assert!(bvh.hit(&r, Interval::new(0.001, f64::INFINITY)).is_none());
+        }
+    }
+}
@@ -1,27 +1,51 @@
 use crate::utility::ray::Ray;
 use crate::utility::vec3::{self,Vec3, Point3};
-use crate::utility::colors::{self,Color};
+use crate::utility::colors::Color;
+use crate::utility::framebuffer::Framebuffer;
 use crate::utility::interval::Interval;
-use crate::utility::hittable::Hittable;
+use crate::utility::hittable::{HitRecord, Hittable};
+use crate::utility::light_list::LightList;
+use crate::utility::material::Scattered;
+use crate::utility::pdf::{HittablePdf, Pdf};
 use crate::utility::common;
-use std::fs::File;
+use crate::utility::aov::Aovs;
+use crate::utility::background::Background;
+
+use std::collections::HashMap;
+use std::sync::Arc;
 
 use rayon::prelude::*;
 
 
+// First hit data of one pixel, accumulated over its samples
 #[derive(Default)]
+struct PixelAovs {
+    hits: usize, // Number of samples that hit something
+    depth: Option<f64>,
+    normal: Vec3,
+    albedo: Color,
+    position: Point3,
+    object_id: Option<usize>,
+    material: Option<usize>, // Address of the material, numbered once the image is complete
+}
+
+
 pub struct Camera {
     pub aspect_ratio: f64, // Ratio of image width over height 
     pub image_width: i32, // Rendered image width in pixel count
     pub image_height: i32, // Rendred image height 
     pub samples_per_pixel: i32, // Count of random samples for each pixel
     pub max_depth: i32, // Maximum number of ray bounces into scene 
+    pub min_depth: i32, // Number of bounces before Russian roulette may end a path
     pub vfov: f64, // Vertical viewing angle (field of view)
     pub look_from: Point3, // Point camera is looking from
     pub look_at: Point3, // Point camera is looking at 
     pub vup: Vec3, // Camera relative "up" direction
     pub defocus_angle: f64, // Variation angle of rays through each pixel
     pub focus_dist: f64, // Distance from camera lookrom point to plane of perfect focus
+    pub seed: Option<u64>, // Seed for reproducible renders, random when unset
+    pub background: Background, // Radiance of rays that leave the scene
+    pub progress: Option<Box<dyn Fn(i32) + Send + Sync>>, // Called with the scanlines left before each one, and 0 when done
         pixel_sample_scale: f64, // Color scale factor for a sum of pixel sample 
         center: Point3, // Camera center 
         pixel00_loc: Point3, // Location of pixel 0, 0
@@ -34,37 +58,135 @@ pub struct Camera {
 }
 
 
+impl Default for Camera {
+    fn default() -> Self {
+        // Zero for everything that has to be set up, except that Russian roulette waits for a
+        // few bounces as it does for scene files and the command line
+        Camera {
+            aspect_ratio: 0.0,
+            image_width: 0,
+            image_height: 0,
+            samples_per_pixel: 0,
+            max_depth: 0,
+            min_depth: 5,
+            vfov: 0.0,
+            look_from: Point3::default(),
+            look_at: Point3::default(),
+            vup: Vec3::default(),
+            defocus_angle: 0.0,
+            focus_dist: 0.0,
+            seed: None,
+            background: Background::default(),
+            progress: None,
+            pixel_sample_scale: 0.0,
+            center: Point3::default(),
+            pixel00_loc: Point3::default(),
+            pixel_delta_u: Vec3::default(),
+            pixel_delta_v: Vec3::default(),
+            u: Vec3::default(),
+            v: Vec3::default(),
+            w: Vec3::default(),
+            defocus_disk_u: Vec3::default(),
+            defocus_disk_v: Vec3::default(),
+        }
+    }
+}
+
 impl Camera {
     pub fn new() -> Self {
         Default::default()
     }
 
-    pub fn render(&mut self, world: &dyn Hittable, output: &mut File) {
+    pub fn render(&self, world: &dyn Hittable, lights: &LightList) -> Framebuffer {
+        // Render the world into a framebuffer of linear radiance, averaged over the pixel samples.
+        // Lights holds the emitters sampled directly at every diffuse bounce, and may be empty
+        return self.render_pass(world, lights, false).0;
+    }
+
+    pub fn render_with_aovs(&self, world: &dyn Hittable, lights: &LightList) -> (Framebuffer, Aovs) {
+        // Render the world along with the auxiliary first hit buffers
+        let (framebuffer, aovs) = self.render_pass(world, lights, true);
+        return (framebuffer, aovs.expect("AOVs were requested"));
+    }
+
+    fn render_pass(&self, world: &dyn Hittable, lights: &LightList, with_aovs: bool) -> (Framebuffer, Option<Aovs>) {
+        let width = self.image_width as usize;
+        let height = self.image_height as usize;
+        let mut framebuffer = Framebuffer::new(width, height);
+        let mut aovs = if with_aovs { Some(Aovs::new(width, height)) } else { None };
+        let mut material_ids: HashMap<usize, usize> = HashMap::new();
 
-        //Render
         for j in 0..self.image_height {
-            println!("Scanlines remaining: {}", self.image_height - j);
+            if let Some(progress) = &self.progress {
+                progress(self.image_height - j);
+            }
             let pixel_colors: Vec<_> = (0..self.image_width)
                 .into_par_iter()
                 .map(|i| {
+                    if let Some(seed) = self.seed {
+                        // Give every pixel its own random sequence, so the image doesn't
+                        // depend on how pixels are spread over threads
+                        let pixel_index = (j as u64) * (self.image_width as u64) + i as u64;
+                        common::seed_rng(seed ^ pixel_index.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+                    }
                     let mut pixel_color = Color::new(0.0,0.0,0.0);
+                    let mut pixel_aovs = PixelAovs::default();
                     for _ in 0..self.samples_per_pixel {
                         let r = self.get_ray(i,j);
-                        pixel_color += Self::ray_color(&r, self.max_depth, world);
+                        let (sample_color, first_hit) = self.ray_color(&r, world, lights);
+                        pixel_color += sample_color;
+                        if let (true, Some(rec)) = (with_aovs, first_hit) {
+                            Self::accumulate_aovs(&r, &rec, &mut pixel_aovs);
+                        }
                     }
-                    pixel_color
+                    (pixel_color, pixel_aovs)
                 })
             .collect();
-            for pixel_color in pixel_colors {
-                colors::write_color(output, self.pixel_sample_scale * pixel_color);
+            for (i, (pixel_color, pixel_aovs)) in pixel_colors.into_iter().enumerate() {
+                framebuffer.set(i, j as usize, self.pixel_sample_scale * pixel_color);
+
+                if let Some(aovs) = aovs.as_mut() {
+                    let index = j as usize * width + i;
+                    // Average over the samples that hit something, so edges aren't darkened by
+                    // the samples that missed
+                    let hit_scale = if pixel_aovs.hits > 0 { 1.0 / pixel_aovs.hits as f64 } else { 0.0 };
+                    aovs.normal.pixels[index] = hit_scale * pixel_aovs.normal;
+                    aovs.albedo.pixels[index] = hit_scale * pixel_aovs.albedo;
+                    aovs.position.pixels[index] = hit_scale * pixel_aovs.position;
+                    aovs.depth[index] = pixel_aovs.depth.unwrap_or(f64::INFINITY);
+                    aovs.object_id[index] = pixel_aovs.object_id;
+                    // Number materials in order of first appearance
+                    aovs.material_id[index] = pixel_aovs.material.map(|address| {
+                        let next_id = material_ids.len();
+                        *material_ids.entry(address).or_insert(next_id)
+                    });
+                }
             }
         }
-        println!("Done!");
+        if let Some(progress) = &self.progress {
+            progress(0);
+        }
+        return (framebuffer, aovs);
+    }
+
+    fn accumulate_aovs(r: &Ray, rec: &HitRecord, pixel_aovs: &mut PixelAovs) {
+        pixel_aovs.hits += 1;
+        pixel_aovs.normal += rec.normal;
+        pixel_aovs.albedo += rec.mat.albedo(rec);
+        pixel_aovs.position += rec.p;
+
+        // Camera ray directions aren't normalized, so scale t to get the distance
+        let distance = rec.t * r.direction().length();
+        if pixel_aovs.depth.is_none_or(|depth| distance < depth) {
+            pixel_aovs.depth = Some(distance);
+            pixel_aovs.object_id = Some(rec.object_id);
+            pixel_aovs.material = Some(Arc::as_ptr(&rec.mat) as *const () as usize);
+        }
     }
 
     pub fn initialize(&mut self) {
         // Image
-        self.image_height = (self.image_width as f64/ self.aspect_ratio) as i32;
+        self.image_height = (self.image_width as f64/ self.aspect_ratio).round() as i32;
         self.image_height = if self.image_height < 1 {1} else {self.image_height};
 
         // Render
@@ -127,24 +249,228 @@ impl Camera {
         return self.center + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v); 
     }
 
-    fn ray_color(r: &Ray, depth: i32,  world: &dyn Hittable) -> Color {
-        // If we've exceeded the ray bounce limit, no more light is gathered
-        if depth < 0 {
+    fn ray_color(&self, r: &Ray, world: &dyn Hittable, lights: &LightList) -> (Color, Option<HitRecord>) {
+        // Follow the path of r through the scene, gathering light at every bounce. Throughput
+        // is the fraction of light at the current vertex that makes it back to the camera.
+        // Also returns the first surface hit, for the AOVs
+        let mut color = Color::from_float(0.0);
+        let mut first_hit = None;
+        let mut throughput = Color::from_float(1.0);
+        let mut ray = *r;
+        // Density with which the previous bounce picked ray, or None for camera rays and
+        // specular bounces
+        let mut pdf: Option<f64> = None;
+
+        for depth in 0..=self.max_depth {
+            let Some(hit_rec) = world.hit(&ray, Interval::new(0.001, common::INFINITY)) else {
+                // If the ray hits nothing, gather the background color
+                let mut background = self.background.value(ray.direction());
+                if let Some(pdf) = pdf {
+                    // This direction could also have come from sampling the background, so only
+                    // count this strategy's share
+                    background *= power_heuristic(pdf, self.background.pdf(ray.direction()));
+                }
+                color += throughput * background;
+                break;
+            };
+            if depth == 0 {
+                first_hit = Some(hit_rec.clone());
+            }
+
+            let mut color_from_emission = hit_rec.mat.emitted(hit_rec.u, hit_rec.v, &hit_rec.p);
+            if let Some(pdf) = pdf {
+                if !lights.is_empty() && !color_from_emission.near_zero() {
+                    // The previous vertex also sampled the lights directly, so only count this
+                    // strategy's share
+                    color_from_emission *= power_heuristic(pdf, lights.pdf_value(ray.origin(), ray.direction()));
+                }
+            }
+            color += throughput * color_from_emission;
+
+            let Some(scatter_rec) = hit_rec.mat.scatter(&ray, &hit_rec) else {
+                break;
+            };
+
+            match scatter_rec.scattered {
+                Scattered::Specular(scattered) => {
+                    throughput = throughput * scatter_rec.attenuation;
+                    ray = scattered;
+                    pdf = None;
+                }
+                Scattered::Pdf(surface_pdf) => {
+                    // Next event estimation: light arriving straight from the lights and the background
+                    let color_from_lights = self.sample_lights(&ray, &hit_rec, scatter_rec.attenuation, surface_pdf.as_ref(), world, lights);
+                    let color_from_background = self.sample_background(&ray, &hit_rec, scatter_rec.attenuation, surface_pdf.as_ref(), world);
+                    let color_from_punctual = Self::sample_punctual_lights(&ray, &hit_rec, scatter_rec.attenuation, world, lights);
+                    color += throughput * (color_from_lights + color_from_background + color_from_punctual);
+
+                    let scattered = Ray::new(hit_rec.p, surface_pdf.generate());
+                    let pdf_value = surface_pdf.value(scattered.direction());
+                    if pdf_value <= 0.0 {
+                        break;
+                    }
+                    let scattering_pdf = hit_rec.mat.scattering_pdf(&ray, &hit_rec, &scattered);
+                    throughput = throughput * scatter_rec.attenuation * scattering_pdf / pdf_value;
+                    ray = scattered;
+                    pdf = Some(pdf_value);
+                }
+            }
+
+            // Russian roulette: past the minimum depth, end dim paths at random and boost the
+            // ones that survive by the same odds, which keeps the estimate unbiased
+            if depth >= self.min_depth {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+                if common::random_double() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+        return (color, first_hit);
+    }
+
+    fn sample_lights(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: Color,
+        surface_pdf: &dyn Pdf,
+        world: &dyn Hittable,
+        lights: &LightList,
+    ) -> Color {
+        // Light from a point picked on one of the lights, if nothing blocks the way, weighted
+        // against the chance that scattering picks the same direction
+        if lights.is_empty() {
             return Color::from_float(0.0);
         }
+        let light_pdf = HittablePdf::new(lights, rec.p);
+        let shadow_ray = Ray::new(rec.p, light_pdf.generate());
+        let pdf_light = light_pdf.value(shadow_ray.direction());
+        let scattering_pdf = rec.mat.scattering_pdf(r_in, rec, &shadow_ray);
+        if pdf_light <= 0.0 || scattering_pdf == 0.0 {
+            return Color::from_float(0.0);
+        }
+
+        // Whatever the shadow ray reaches first is the light seen in that direction
+        let Some(light_rec) = world.hit(&shadow_ray, Interval::new(0.001, common::INFINITY)) else {
+            return Color::from_float(0.0);
+        };
+        let emitted = light_rec.mat.emitted(light_rec.u, light_rec.v, &light_rec.p);
 
-        if let Some(hit_rec) = world.hit(r, Interval::new(0.001, common::INFINITY)) {
-           if let Some(scatter_rec) = hit_rec.mat.scatter(r, &hit_rec) {
-               return scatter_rec.attenuation * Self::ray_color(&scatter_rec.scattered, depth-1, world);
+        // attenuation * scattering_pdf is the BRDF times the cosine term
+        let weight = power_heuristic(pdf_light, surface_pdf.value(shadow_ray.direction()));
+        return weight * scattering_pdf / pdf_light * attenuation * emitted;
+    }
+
+    fn sample_punctual_lights(
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: Color,
+        world: &dyn Hittable,
+        lights: &LightList,
+    ) -> Color {
+        // Light from every punctual light that isn't blocked. Scattered rays can never hit
+        // them, so there is nothing to weigh these samples against
+        let mut color = Color::from_float(0.0);
+        for light in lights.punctual_lights() {
+            let Some((direction, distance, radiance)) = light.sample(rec.p) else { continue };
+
+            let shadow_ray = Ray::new(rec.p, direction);
+            let scattering_pdf = rec.mat.scattering_pdf(r_in, rec, &shadow_ray);
+            if scattering_pdf == 0.0 || world.hit(&shadow_ray, Interval::new(0.001, distance - 0.001)).is_some() {
+                continue;
             }
-            return Color::new(0.0,0.0,0.0);
+            color += scattering_pdf * attenuation * radiance;
         }
+        return color;
+    }
 
-        let unit_direction: Vec3 = vec3::unit_vector(r.direction());
-        let a = 0.5*(unit_direction.y + 1.0);
-        return (1.0 - a) * Color::new(1.0,1.0,1.0) + a*Color::new(0.5,0.7,1.0);
+    fn sample_background(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: Color,
+        surface_pdf: &dyn Pdf,
+        world: &dyn Hittable,
+    ) -> Color {
+        // Light from a direction picked towards the bright parts of the background, weighted
+        // against the chance that scattering picks the same direction
+        let Some((direction, light_pdf)) = self.background.sample() else {
+            return Color::from_float(0.0);
+        };
+
+        let shadow_ray = Ray::new(rec.p, direction);
+        let scattering_pdf = rec.mat.scattering_pdf(r_in, rec, &shadow_ray);
+        if scattering_pdf == 0.0 || world.hit(&shadow_ray, Interval::new(0.001, common::INFINITY)).is_some() {
+            return Color::from_float(0.0);
+        }
+
+        let weight = power_heuristic(light_pdf, surface_pdf.value(direction));
+        return weight * scattering_pdf / light_pdf * attenuation * self.background.value(direction);
     }
 }
 
 
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    // Multiple importance sampling weight of a sample drawn with pdf, when other_pdf could
+    // have produced it too
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    return if a + b == 0.0 { 0.0 } else { a / (a + b) };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::hittable_list::HittableList;
+    use crate::utility::material::Lambertian;
+    use crate::utility::punctual_light::PunctualLight;
+    use crate::utility::sphere::Sphere;
+
+    fn furnace_radiance(min_depth: i32) -> f64 {
+        // The camera and a point light sit at the center of a closed diffuse sphere. Every
+        // bounce reflects the same fraction of the light, so all paths of the same length
+        // carry the same radiance and only Russian roulette makes the estimate vary
+        let albedo = 0.5;
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(Point3::default(), 1.0, Arc::new(Lambertian::new(Color::from_float(albedo))))));
+        let light = PunctualLight::point(Point3::default(), Color::from_float(1.0));
+        let lights = LightList::new(Vec::new()).with_punctual_lights(vec![light]);
+
+        let mut camera = Camera::new();
+        camera.aspect_ratio = 1.0;
+        camera.image_width = 8;
+        camera.samples_per_pixel = 64;
+        camera.max_depth = 40;
+        camera.min_depth = min_depth;
+        camera.vfov = 90.0;
+        camera.look_from = Point3::default();
+        camera.look_at = Point3::new(0.0, 0.0, -1.0);
+        camera.vup = Vec3::new(0.0, 1.0, 0.0);
+        camera.focus_dist = 1.0;
+        camera.seed = Some(17);
+        camera.initialize();
+        let framebuffer = camera.render(&world, &lights);
+        return framebuffer.pixels.iter().map(|c| c.x).sum::<f64>() / framebuffer.pixels.len() as f64;
+    }
+
+    #[test]
+    fn russian_roulette_preserves_the_furnace_radiance() {
+        // The wall receives I / r^2 straight from the light, and the sphere reflects a fraction
+        // albedo of its irradiance back onto itself evenly, so the total irradiance is
+        // E = 1 / (1 - albedo) and the wall shows radiance albedo * E / pi
+        let expected = 0.5 * 2.0 / common::PI;
 
+        let without_roulette = furnace_radiance(40);
+        assert!((without_roulette - expected).abs() < 1e-6 * expected, "{} vs {}", without_roulette, expected);
+        for min_depth in [0, 2, Camera::new().min_depth] {
+            let radiance = furnace_radiance(min_depth);
+            assert!((radiance - expected).abs() < 0.02 * expected, "min_depth {}: {} vs {}", min_depth, radiance, expected);
+        }
+    }
+
+    #[test]
+    fn default_roulette_depth_matches_scene_files() {
+        assert_eq!(Camera::default().min_depth, 5);
+    }
+}
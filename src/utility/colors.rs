@@ -1,31 +1,44 @@
 use std::io::Write;
-use std::fs::File;
 use crate::utility::vec3::Vec3;
 use crate::utility::interval::Interval;
 
 pub type Color = Vec3;
 
-fn linear_to_gamma(linear_component: f64) -> f64 {
-    if linear_component > 0.0 {
-        return f64::sqrt(linear_component);
+pub fn luminance(c: Color) -> f64 {
+    // Relative luminance of linear Rec. 709 primaries
+    return 0.2126*c.x + 0.7152*c.y + 0.0722*c.z;
+}
+
+pub fn linear_to_srgb(linear_component: f64) -> f64 {
+    // The sRGB transfer function: linear near black, then a 1/2.4 power curve
+    if linear_component <= 0.0031308 {
+        return 12.92 * f64::max(linear_component, 0.0);
+    }
+    return 1.055 * f64::powf(linear_component, 1.0 / 2.4) - 0.055;
+}
+
+pub fn srgb_to_linear(srgb_component: f64) -> f64 {
+    // Inverse of the sRGB transfer function, for colors read from 8 bit images
+    if srgb_component <= 0.04045 {
+        return srgb_component / 12.92;
     }
-    return 0.0;
+    return f64::powf((srgb_component + 0.055) / 1.055, 2.4);
 }
 
-pub fn write_color(output: &mut File, pixel_color: Color) {
+pub fn write_color(output: &mut impl Write, pixel_color: Color) -> std::io::Result<()> {
     let mut r = pixel_color.x;
     let mut g = pixel_color.y;
     let mut b = pixel_color.z;
 
-    //Apply a linear to gamma transform for gamma 2
-    r = linear_to_gamma(r);
-    g = linear_to_gamma(g);
-    b = linear_to_gamma(b);
+    // Apply the sRGB transfer function to the linear display values
+    r = linear_to_srgb(r);
+    g = linear_to_srgb(g);
+    b = linear_to_srgb(b);
 
     // Translate the [0,1] component values to the byte range [0,255]
     let intensity = Interval::new(0.000, 0.999);
     let r = (256.0 * intensity.clamp(r)) as i32;
     let g = (256.0 * intensity.clamp(g)) as i32;
     let b = (256.0 * intensity.clamp(b)) as i32;
-    writeln!(output, "{} {} {}", r, g, b).expect("writing color");
+    writeln!(output, "{} {} {}", r, g, b)
 }
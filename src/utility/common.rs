@@ -1,8 +1,11 @@
-use rand::Rng;
+use std::cell::RefCell;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 //Constans
 pub use std::f64::consts::PI;
-pub use std::f64::INFINITY;
+pub const INFINITY: f64 = f64::INFINITY;
 
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
@@ -10,9 +13,20 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
 }
 
 
+thread_local! {
+    // Per-thread generator behind every random number in the renderer. It starts from entropy,
+    // and is reseeded for reproducible renders
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+pub fn seed_rng(seed: u64) {
+    // Reseed the random number generator of the calling thread
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
 pub fn random_double() -> f64 {
     // Return a random real in the range [0.0, 1.0)
-    rand::thread_rng().gen()
+    RNG.with(|rng| rng.borrow_mut().gen())
 }
 
 pub fn random_double_range(min: f64, max: f64) -> f64 {
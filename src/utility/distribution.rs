@@ -0,0 +1,238 @@
+// Piecewise-constant probability distributions, used to pick samples in proportion to a
+// tabulated function such as the brightness of an image
+
+
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>, // One entry more than func, from 0 up to 1
+    integral: f64, // Integral of the step function over [0,1]
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        assert!(n > 0, "distributions need at least one value");
+
+        // Integrate the step function, then normalize the running sum into a CDF
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].abs() / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, value) in cdf.iter_mut().enumerate().skip(1) {
+            // A function that is zero everywhere falls back to a uniform distribution
+            *value = if integral == 0.0 { i as f64 / n as f64 } else { *value / integral };
+        }
+
+        Distribution1D { func: func, cdf: cdf, integral: integral }
+    }
+
+    pub fn count(&self) -> usize {
+        return self.func.len();
+    }
+
+    pub fn integral(&self) -> f64 {
+        return self.integral;
+    }
+
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        // Map a uniform u in [0,1) to a point x in [0,1) following the distribution. Returns
+        // x, its density, and the index of the step it falls in
+        let offset = self.cdf.partition_point(|&c| c <= u).clamp(1, self.count()) - 1;
+
+        // How far u lies within its step of the CDF
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+
+        let pdf = if self.integral > 0.0 { self.func[offset].abs() / self.integral } else { 1.0 };
+        return ((offset as f64 + du) / self.count() as f64, pdf, offset);
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        // Density at x in [0,1)
+        if self.integral == 0.0 {
+            return 1.0;
+        }
+        let offset = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        return self.func[offset].abs() / self.integral;
+    }
+}
+
+
+// A distribution over [0,1]^2, sampled by first picking a row from the marginal distribution,
+// then a column from that row's conditional distribution
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>, // One per row
+    marginal: Distribution1D, // Over rows, by their integrals
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        assert_eq!(func.len(), width * height, "function size doesn't match the grid size");
+
+        let conditional: Vec<Distribution1D> = func
+            .chunks(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|row| row.integral()).collect());
+
+        Distribution2D { conditional: conditional, marginal: marginal }
+    }
+
+    pub fn sample(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        // Returns a point (x, y) in [0,1)^2 with its density. The row is chosen with u2
+        let (y, pdf_row, row) = self.marginal.sample_continuous(u2);
+        let (x, pdf_column, _) = self.conditional[row].sample_continuous(u1);
+        return ((x, y), pdf_row * pdf_column);
+    }
+
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let row = ((y * self.marginal.count() as f64) as usize).min(self.marginal.count() - 1);
+        return self.marginal.pdf(y) * self.conditional[row].pdf(x);
+    }
+}
+
+
+// Discrete distribution over indices in proportion to their weights, sampled in constant time
+// with Vose's alias method. Each slot keeps its own index with some probability and otherwise
+// hands over to its alias.
+pub struct AliasTable {
+    probability: Vec<f64>, // Chance that a slot keeps its own index
+    alias: Vec<usize>,
+    pmf: Vec<f64>, // Normalized weights
+}
+
+impl AliasTable {
+    pub fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        assert!(n > 0, "alias tables need at least one weight");
+
+        // Weights that are all zero fall back to a uniform distribution
+        let total: f64 = weights.iter().map(|w| w.abs()).sum();
+        let pmf: Vec<f64> = if total > 0.0 {
+            weights.iter().map(|w| w.abs() / total).collect()
+        } else {
+            vec![1.0 / n as f64; n]
+        };
+
+        // Scale the weights so the average is one, then pair each slot below one with a slot
+        // above one that tops it up
+        let mut scaled: Vec<f64> = pmf.iter().map(|p| p * n as f64).collect();
+        let mut probability = vec![1.0; n];
+        let mut alias: Vec<usize> = (0..n).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| scaled[i] < 1.0);
+
+        while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+            small.pop();
+            probability[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // Whatever is left over is one up to rounding errors and keeps its own index
+
+        AliasTable { probability: probability, alias: alias, pmf: pmf }
+    }
+
+    pub fn count(&self) -> usize {
+        return self.pmf.len();
+    }
+
+    pub fn sample(&self, u: f64) -> usize {
+        // Map a uniform u in [0,1) to an index. The integer part of u*n picks the slot and the
+        // fraction decides between the slot and its alias
+        let scaled = u * self.count() as f64;
+        let slot = (scaled as usize).min(self.count() - 1);
+        let fraction = scaled - slot as f64;
+        return if fraction < self.probability[slot] { slot } else { self.alias[slot] };
+    }
+
+    pub fn pmf(&self, index: usize) -> f64 {
+        return self.pmf[index];
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stratified uniform samples, so the observed frequencies are deterministic and close to
+    // exact for a correct mapping
+    const SAMPLES: usize = 400;
+
+    fn strata() -> impl Iterator<Item = f64> {
+        (0..SAMPLES).map(|i| (i as f64 + 0.5) / SAMPLES as f64)
+    }
+
+    #[test]
+    fn alias_table_samples_in_proportion_to_the_weights() {
+        let weights = [1.0, 0.0, 3.0, 6.0, 0.5, 2.5];
+        let table = AliasTable::new(&weights);
+        let total: f64 = weights.iter().sum();
+
+        let mut counts = [0usize; 6];
+        for u in (0..SAMPLES * SAMPLES).map(|i| (i as f64 + 0.5) / (SAMPLES * SAMPLES) as f64) {
+            counts[table.sample(u)] += 1;
+        }
+        for (i, &count) in counts.iter().enumerate() {
+            assert!((table.pmf(i) - weights[i] / total).abs() < 1e-12);
+            let frequency = count as f64 / (SAMPLES * SAMPLES) as f64;
+            assert!((frequency - table.pmf(i)).abs() < 1e-4, "index {}: {} vs {}", i, frequency, table.pmf(i));
+        }
+        assert_eq!(counts[1], 0);
+    }
+
+    #[test]
+    fn alias_table_without_weight_is_uniform() {
+        let table = AliasTable::new(&[0.0; 4]);
+        assert!((0..4).all(|i| table.pmf(i) == 0.25));
+        assert_eq!(strata().map(|u| table.sample(u)).filter(|&i| i == 3).count(), SAMPLES / 4);
+    }
+
+    #[test]
+    fn distribution_2d_samples_in_proportion_to_the_function() {
+        let (width, height) = (4, 3);
+        let func = [1.0, 2.0, 0.0, 4.0, 0.0, 0.0, 0.0, 0.0, 8.0, 1.0, 1.0, 3.0];
+        let distribution = Distribution2D::new(&func, width, height);
+        let mean = func.iter().sum::<f64>() / func.len() as f64;
+
+        let mut counts = [0usize; 12];
+        for u2 in strata() {
+            for u1 in strata() {
+                let ((x, y), pdf) = distribution.sample(u1, u2);
+                assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                let cell = (y * height as f64) as usize * width + (x * width as f64) as usize;
+                counts[cell] += 1;
+
+                // The density of a sample is the function over its mean, and matches pdf()
+                assert!((pdf - func[cell] / mean).abs() < 1e-9, "cell {}: pdf {}", cell, pdf);
+                assert!((distribution.pdf(x, y) - pdf).abs() < 1e-9);
+            }
+        }
+        for (cell, &count) in counts.iter().enumerate() {
+            let frequency = count as f64 / (SAMPLES * SAMPLES) as f64;
+            let expected = func[cell] / (mean * func.len() as f64);
+            assert!((frequency - expected).abs() < 1e-2, "cell {}: {} vs {}", cell, frequency, expected);
+        }
+    }
+
+    #[test]
+    fn distribution_2d_pdf_integrates_to_one() {
+        // The strata line up with the cell edges, so the midpoint sum is exact
+        let func: Vec<f64> = (0..5 * 4).map(|i| ((i * 7) % 11) as f64).collect();
+        let distribution = Distribution2D::new(&func, 5, 4);
+        let integral: f64 = strata()
+            .flat_map(|y| strata().map(move |x| (x, y)))
+            .map(|(x, y)| distribution.pdf(x, y))
+            .sum::<f64>()
+            / (SAMPLES * SAMPLES) as f64;
+        assert!((integral - 1.0).abs() < 1e-9, "{}", integral);
+    }
+}
@@ -0,0 +1,33 @@
+use crate::utility::colors::Color;
+
+
+// Owned image of linear radiance values, stored row by row from the top left pixel
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        // Create a black framebuffer of the given size
+        Framebuffer {
+            width: width,
+            height: height,
+            pixels: vec![Color::from_float(0.0); width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        return self.pixels[y * self.width + x];
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    pub fn rows(&self) -> std::slice::Chunks<'_, Color> {
+        return self.pixels.chunks(self.width);
+    }
+}
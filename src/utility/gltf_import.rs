@@ -0,0 +1,391 @@
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use gltf::camera::Projection;
+use gltf::mesh::Mode;
+
+use crate::utility::camera::Camera;
+use crate::utility::colors::Color;
+use crate::utility::common;
+use crate::utility::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::utility::triangle::{MeshData, TriangleMesh};
+use crate::utility::vec3::{self, Point3, Vec3};
+
+
+#[derive(Debug)]
+pub enum GltfError {
+    Import { path: PathBuf, source: gltf::Error },
+    Invalid { path: PathBuf, message: String },
+}
+
+impl Display for GltfError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            GltfError::Import { path, source } => write!(f, "{}: {}", path.display(), source),
+            GltfError::Invalid { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Import { source, .. } => Some(source),
+            GltfError::Invalid { .. } => None,
+        }
+    }
+}
+
+
+// Column-major 4x4 matrix, matching glTF's storage order
+type Mat4 = [[f64; 4]; 4];
+
+const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+fn mat_mul(a: &Mat4, b: &Mat4) -> Mat4 {
+    let mut m = [[0.0; 4]; 4];
+    for (col, b_col) in b.iter().enumerate() {
+        for row in 0..4 {
+            m[col][row] = (0..4).map(|k| a[k][row] * b_col[k]).sum();
+        }
+    }
+    return m;
+}
+
+fn transform_point(m: &Mat4, p: Point3) -> Point3 {
+    Point3::new(
+        m[0][0]*p.x + m[1][0]*p.y + m[2][0]*p.z + m[3][0],
+        m[0][1]*p.x + m[1][1]*p.y + m[2][1]*p.z + m[3][1],
+        m[0][2]*p.x + m[1][2]*p.y + m[2][2]*p.z + m[3][2],
+    )
+}
+
+fn transform_vector(m: &Mat4, v: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0]*v.x + m[1][0]*v.y + m[2][0]*v.z,
+        m[0][1]*v.x + m[1][1]*v.y + m[2][1]*v.z,
+        m[0][2]*v.x + m[1][2]*v.y + m[2][2]*v.z,
+    )
+}
+
+fn transform_normal(m: &Mat4, n: Vec3) -> Vec3 {
+    // Normals transform by the inverse transpose of the upper 3x3 block. The cofactor matrix
+    // equals it up to a scale factor, which is removed by normalizing anyway
+    let c0 = Vec3::new(m[0][0], m[0][1], m[0][2]);
+    let c1 = Vec3::new(m[1][0], m[1][1], m[1][2]);
+    let c2 = Vec3::new(m[2][0], m[2][1], m[2][2]);
+    let r0 = vec3::cross(c1, c2);
+    let r1 = vec3::cross(c2, c0);
+    let r2 = vec3::cross(c0, c1);
+    let det = vec3::dot(c0, r0);
+    let transformed = n.x * r0 + n.y * r1 + n.z * r2;
+    return if det < 0.0 { -transformed } else { transformed };
+}
+
+fn to_mat4(m: [[f32; 4]; 4]) -> Mat4 {
+    m.map(|col| col.map(|x| x as f64))
+}
+
+
+// View parameters of a glTF camera, ready to be copied onto a renderer camera
+#[derive(Debug, Clone, Copy)]
+pub struct GltfCamera {
+    pub vfov: f64,
+    pub aspect_ratio: Option<f64>,
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vup: Vec3,
+}
+
+impl GltfCamera {
+    pub fn apply(&self, camera: &mut Camera) {
+        // Only the view is taken from the file. Image size, sampling and focus settings stay
+        // as configured on the camera
+        camera.vfov = self.vfov;
+        camera.look_from = self.look_from;
+        camera.look_at = self.look_at;
+        camera.vup = self.vup;
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            camera.aspect_ratio = aspect_ratio;
+        }
+    }
+}
+
+
+pub struct GltfScene {
+    pub meshes: Vec<TriangleMesh>, // One per triangle primitive
+    pub camera: Option<GltfCamera>,
+}
+
+
+struct Importer<'a> {
+    path: &'a Path,
+    buffers: Vec<gltf::buffer::Data>,
+    materials: Vec<Arc<dyn Material>>,
+    default_material: Arc<dyn Material>,
+    scene: GltfScene,
+}
+
+impl Importer<'_> {
+    fn invalid(&self, message: String) -> GltfError {
+        GltfError::Invalid { path: self.path.to_path_buf(), message: message }
+    }
+
+    fn visit(&mut self, node: gltf::Node, parent: &Mat4) -> Result<(), GltfError> {
+        let transform = mat_mul(parent, &to_mat4(node.transform().matrix()));
+
+        if let Some(mesh) = node.mesh() {
+            self.add_mesh(&mesh, &transform)?;
+        }
+
+        if let (Some(camera), None) = (node.camera(), &self.scene.camera) {
+            if let Projection::Perspective(perspective) = camera.projection() {
+                // glTF cameras look down their local -z axis with +y up
+                let look_from = transform_point(&transform, Point3::new(0.0, 0.0, 0.0));
+                let forward = vec3::unit_vector(transform_vector(&transform, Vec3::new(0.0, 0.0, -1.0)));
+                self.scene.camera = Some(GltfCamera {
+                    vfov: perspective.yfov() as f64 * 180.0 / common::PI,
+                    aspect_ratio: perspective.aspect_ratio().map(|a| a as f64),
+                    look_from: look_from,
+                    look_at: look_from + forward,
+                    vup: transform_vector(&transform, Vec3::new(0.0, 1.0, 0.0)),
+                });
+            }
+        }
+
+        for child in node.children() {
+            self.visit(child, &transform)?;
+        }
+        return Ok(());
+    }
+
+    fn add_mesh(&mut self, mesh: &gltf::Mesh, transform: &Mat4) -> Result<(), GltfError> {
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| self.buffers.get(buffer.index()).map(|data| &data.0[..]));
+
+            let positions: Vec<Point3> = match reader.read_positions() {
+                Some(iter) => iter.map(|p| transform_point(transform, Point3::new(p[0] as f64, p[1] as f64, p[2] as f64))).collect(),
+                None => return Err(self.invalid(format!("mesh {} has a primitive without positions", mesh.index()))),
+            };
+            let normals: Vec<Vec3> = match reader.read_normals() {
+                Some(iter) => iter.map(|n| transform_normal(transform, Vec3::new(n[0] as f64, n[1] as f64, n[2] as f64))).collect(),
+                None => Vec::new(),
+            };
+            let uvs: Vec<(f64, f64)> = match reader.read_tex_coords(0) {
+                Some(iter) => iter.into_f32().map(|t| (t[0] as f64, t[1] as f64)).collect(),
+                None => Vec::new(),
+            };
+            let vertex_indices: Vec<usize> = match reader.read_indices() {
+                Some(iter) => iter.into_u32().map(|i| i as usize).collect(),
+                None => (0..positions.len()).collect(),
+            };
+
+            let indices: Vec<[usize; 3]> = match primitive.mode() {
+                Mode::Triangles => vertex_indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect(),
+                Mode::TriangleStrip => (2..vertex_indices.len())
+                    .map(|k| {
+                        // Every other triangle of a strip has reversed winding
+                        if k % 2 == 0 {
+                            [vertex_indices[k - 2], vertex_indices[k - 1], vertex_indices[k]]
+                        } else {
+                            [vertex_indices[k - 1], vertex_indices[k - 2], vertex_indices[k]]
+                        }
+                    })
+                    .collect(),
+                Mode::TriangleFan => (2..vertex_indices.len())
+                    .map(|k| [vertex_indices[0], vertex_indices[k - 1], vertex_indices[k]])
+                    .collect(),
+                // Points and lines have no surface to render
+                _ => continue,
+            };
+            if indices.is_empty() {
+                continue;
+            }
+
+            let pbr = primitive.material().pbr_metallic_roughness();
+            let base_color = pbr.base_color_factor();
+            let base_color = Color::new(base_color[0] as f64, base_color[1] as f64, base_color[2] as f64);
+
+            let mut material = match primitive.material().index() {
+                Some(index) => self.materials[index].clone(),
+                None => self.default_material.clone(),
+            };
+
+            // Vertex colors tint the base color of diffuse materials
+            let mut colors = Vec::new();
+            let emissive = primitive.material().emissive_factor().iter().any(|&c| c > 0.0);
+            let diffuse = pbr.metallic_factor() < 0.5 && primitive.material().transmission().is_none() && !emissive;
+            if let (Some(iter), true) = (reader.read_colors(0), diffuse) {
+                colors = iter.into_rgb_f32().map(|c| base_color * Color::new(c[0] as f64, c[1] as f64, c[2] as f64)).collect();
+                material = Arc::new(Lambertian::vertex_colored(base_color));
+            }
+
+            // Optional attributes need one value per vertex, or the mesh can't be built
+            for (name, count) in [("NORMAL", normals.len()), ("TEXCOORD_0", uvs.len()), ("COLOR_0", colors.len())] {
+                if count != 0 && count != positions.len() {
+                    return Err(self.invalid(format!(
+                        "mesh {} has {} {} values for {} positions", mesh.index(), count, name, positions.len()
+                    )));
+                }
+            }
+
+            self.scene.meshes.push(TriangleMesh::from_data(MeshData {
+                positions: positions,
+                normals: normals,
+                uvs: uvs,
+                colors: colors,
+                indices: indices,
+                material: material,
+            }).map_err(|e| self.invalid(format!("mesh {}: {}", mesh.index(), e)))?);
+        }
+        return Ok(());
+    }
+}
+
+
+fn convert_material(material: &gltf::Material) -> Arc<dyn Material> {
+    // Map the metallic-roughness model onto the closest renderer material. Transmissive
+    // surfaces become glass, mostly metallic ones metal with roughness as fuzz, and
+    // everything else diffuse with the base color as albedo. Emissive surfaces become lights
+    let pbr = material.pbr_metallic_roughness();
+    let base = pbr.base_color_factor();
+    let base_color = Color::new(base[0] as f64, base[1] as f64, base[2] as f64);
+
+    let emissive = material.emissive_factor();
+    if emissive.iter().any(|&c| c > 0.0) {
+        return Arc::new(DiffuseLight::new(Color::new(emissive[0] as f64, emissive[1] as f64, emissive[2] as f64)));
+    }
+
+    let transmission = material.transmission().map_or(0.0, |t| t.transmission_factor());
+    if transmission >= 0.5 {
+        return Arc::new(Dielectric::new(material.ior().unwrap_or(1.5) as f64));
+    }
+    if pbr.metallic_factor() >= 0.5 {
+        return Arc::new(Metal::new(base_color, pbr.roughness_factor() as f64));
+    }
+    return Arc::new(Lambertian::new(base_color));
+}
+
+
+pub fn load_gltf(path: impl AsRef<Path>) -> Result<GltfScene, GltfError> {
+    // Import the meshes and first perspective camera of the default scene from a .gltf or
+    // .glb file. External buffers are resolved relative to the file, images are not loaded
+    let path = path.as_ref();
+    let import_error = |e: gltf::Error| GltfError::Import { path: path.to_path_buf(), source: e };
+
+    let gltf::Gltf { document, blob } = gltf::Gltf::open(path).map_err(import_error)?;
+    let buffers = gltf::import_buffers(&document, path.parent(), blob).map_err(import_error)?;
+
+    let mut importer = Importer {
+        path: path,
+        buffers: buffers,
+        materials: document.materials().map(|m| convert_material(&m)).collect(),
+        default_material: Arc::new(Lambertian::new(Color::from_float(0.8))),
+        scene: GltfScene { meshes: Vec::new(), camera: None },
+    };
+
+    let scene = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene,
+        None => return Err(importer.invalid("file contains no scene".to_string())),
+    };
+    for node in scene.nodes() {
+        importer.visit(node, &IDENTITY)?;
+    }
+    return Ok(importer.scene);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+
+    fn write_gltf(name: &str, normal_count: usize, indices: [u16; 3]) -> PathBuf {
+        // A single triangle with normals and indices in an external buffer, seen by a camera
+        // two units up the z axis
+        let dir = std::env::temp_dir().join(format!("gltf_test_{}_{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut buffer = Vec::new();
+        for p in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            buffer.extend(p.iter().flat_map(|x| x.to_le_bytes()));
+        }
+        for _ in 0..normal_count {
+            buffer.extend([0.0f32, 0.0, 1.0].iter().flat_map(|x| x.to_le_bytes()));
+        }
+        let index_offset = buffer.len();
+        buffer.extend(indices.iter().flat_map(|i| i.to_le_bytes()));
+        buffer.extend([0, 0]);
+        fs::write(dir.join("mesh.bin"), &buffer).unwrap();
+
+        let document = json!({
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0, 1] }],
+            "nodes": [
+                { "mesh": 0, "translation": [0.0, 0.0, -1.0] },
+                { "camera": 0, "translation": [0.0, 0.0, 2.0] }
+            ],
+            "cameras": [{ "type": "perspective", "perspective": { "yfov": 0.5, "znear": 0.1, "aspectRatio": 2.0 } }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "NORMAL": 1 }, "indices": 2 }] }],
+            "buffers": [{ "byteLength": buffer.len(), "uri": "mesh.bin" }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 12 * normal_count },
+                { "buffer": 0, "byteOffset": index_offset, "byteLength": 6 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+                { "bufferView": 1, "componentType": 5126, "count": normal_count, "type": "VEC3" },
+                { "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }
+            ]
+        });
+        let path = dir.join("triangle.gltf");
+        fs::write(&path, document.to_string()).unwrap();
+        return path;
+    }
+
+    fn invalid_message(result: Result<GltfScene, GltfError>) -> String {
+        match result {
+            Err(GltfError::Invalid { message, .. }) => message,
+            Err(e) => panic!("expected an invalid file error, got {}", e),
+            Ok(_) => panic!("expected an invalid file error"),
+        }
+    }
+
+    #[test]
+    fn imports_meshes_and_the_camera() {
+        let scene = load_gltf(write_gltf("valid", 3, [0, 1, 2])).unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+        let data = scene.meshes[0].data();
+        assert_eq!(data.indices, vec![[0, 1, 2]]);
+        assert_eq!(data.normals.len(), 3);
+        // Node transforms are applied to the vertices
+        assert_eq!(data.positions[1].z, -1.0);
+
+        let camera = scene.camera.expect("the file has a perspective camera");
+        assert_eq!(camera.look_from.z, 2.0);
+        assert_eq!(camera.look_at.z, 1.0);
+        assert_eq!(camera.aspect_ratio, Some(2.0));
+        assert!((camera.vfov - 0.5 * 180.0 / common::PI).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rejects_indices_out_of_range() {
+        let message = invalid_message(load_gltf(write_gltf("bad_index", 3, [0, 1, 5])));
+        assert!(message.contains("references vertex 5 of 3"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_attribute_counts_that_differ_from_the_positions() {
+        let message = invalid_message(load_gltf(write_gltf("short_normals", 2, [0, 1, 2])));
+        assert!(message.contains("2 NORMAL values for 3 positions"), "{}", message);
+    }
+}
@@ -4,13 +4,21 @@ use crate::utility::material::Material;
 use crate::utility::ray::Ray;
 use crate::utility::vec3::{self,Vec3, Point3};
 use crate::utility::interval::Interval;
+use crate::utility::aabb::Aabb;
+use crate::utility::colors::Color;
 
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
     pub mat: Arc<dyn Material>,
     pub t: f64,
+    pub u: f64, // Surface coordinates of the hit point, used for texture lookups
+    pub v: f64,
+    pub barycentric: (f64, f64), // Weights of the second and third vertex for triangle hits
+    pub color: Option<Color>, // Interpolated vertex color, for meshes that carry one
+    pub object_id: usize, // Index of the hit object in the outermost list or BVH containing it
     pub front_face: bool
 }
 
@@ -27,4 +35,17 @@ impl HitRecord {
 
 pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord>;
+
+    fn bounding_box(&self) -> Aabb;
+
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3) -> f64 {
+        // Solid angle density of random() picking the direction from origin. Only objects
+        // that can be sampled as lights implement this and random
+        0.0
+    }
+
+    fn random(&self, _origin: Point3) -> Vec3 {
+        // Random direction from origin towards the object
+        Vec3::new(1.0, 0.0, 0.0)
+    }
 }
@@ -1,22 +1,43 @@
 use crate::utility::hittable::{Hittable, HitRecord};
 use crate::utility::ray::Ray;
 use crate::utility::interval::Interval;
+use crate::utility::aabb::Aabb;
+use crate::utility::common;
+use crate::utility::vec3::{Point3, Vec3};
 
 
 
-#[derive(Default)]
 pub struct HittableList {
-   objects: Vec<Box<dyn Hittable>>, 
+   pub objects: Vec<Box<dyn Hittable>>, 
+   bbox: Aabb,
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HittableList {
     pub fn new() -> Self {
-        Default::default()
+        HittableList {
+            objects: Vec::new(),
+            bbox: Aabb::EMPTY,
+        }
     }
 
     pub fn add(&mut self, object: Box<dyn Hittable>) {
+        self.bbox = Aabb::surrounding(&self.bbox, &object.bounding_box());
         self.objects.push(object);
     }
+
+    pub fn len(&self) -> usize {
+        return self.objects.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.objects.is_empty();
+    }
 }
 
 
@@ -25,12 +46,35 @@ impl Hittable for HittableList {
         let mut temp_rec = None;
         let mut closest_so_far = ray_t.max;
 
-        for object in &self.objects {
-            if let Some(rec) = object.hit(r, Interval::new(ray_t.min, closest_so_far)) {
+        for (index, object) in self.objects.iter().enumerate() {
+            if let Some(mut rec) = object.hit(r, Interval::new(ray_t.min, closest_so_far)) {
                 closest_so_far = rec.t;
+                rec.object_id = index;
                 temp_rec = Some(rec);
             }
         }
         return temp_rec;
     }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        // Each object is picked with equal chance. An empty list never produces a direction
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let weight = 1.0 / self.objects.len() as f64;
+        return self.objects.iter().map(|object| weight * object.pdf_value(origin, direction)).sum();
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        if self.objects.is_empty() {
+            // Any direction will do, since its density is zero
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        let index = (common::random_double() * self.objects.len() as f64) as usize;
+        return self.objects[index.min(self.objects.len() - 1)].random(origin);
+    }
 }
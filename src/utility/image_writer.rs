@@ -0,0 +1,368 @@
+use std::io::{self, Cursor, Write};
+
+use exr::prelude::{f16, AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec, WritableImage};
+
+use crate::utility::colors;
+use crate::utility::framebuffer::Framebuffer;
+use crate::utility::interval::Interval;
+use crate::utility::tonemap::ToneMapping;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PngBitDepth {
+    Eight,
+    Sixteen,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+// One named channel of an OpenEXR image, with a sample per pixel in framebuffer order.
+// Dotted names such as "normal.X" group channels into layers
+pub struct ExrChannel {
+    pub name: String,
+    pub samples: Vec<f32>,
+}
+
+impl ExrChannel {
+    pub fn new(name: impl Into<String>, samples: Vec<f32>) -> Self {
+        ExrChannel { name: name.into(), samples: samples }
+    }
+
+    pub fn rgb(prefix: &str, framebuffer: &Framebuffer) -> [ExrChannel; 3] {
+        // Split a framebuffer into its R, G and B channels, optionally grouped under a layer
+        let name = |channel: &str| if prefix.is_empty() { channel.to_string() } else { format!("{}.{}", prefix, channel) };
+        [
+            ExrChannel::new(name("R"), framebuffer.pixels.iter().map(|c| c.x as f32).collect()),
+            ExrChannel::new(name("G"), framebuffer.pixels.iter().map(|c| c.y as f32).collect()),
+            ExrChannel::new(name("B"), framebuffer.pixels.iter().map(|c| c.z as f32).collect()),
+        ]
+    }
+}
+
+
+pub fn write_ppm(framebuffer: &Framebuffer, output: &mut impl Write, tone_mapping: &ToneMapping) -> std::io::Result<()> {
+    // Image is in .ppm format, first 2 lines are the header
+    writeln!(output, "P3")?;
+    writeln!(output, "{} {}\n255", framebuffer.width, framebuffer.height)?;
+    for &pixel_color in &framebuffer.pixels {
+        colors::write_color(output, tone_mapping.apply(pixel_color))?;
+    }
+    Ok(())
+}
+
+pub fn write_png(
+    framebuffer: &Framebuffer,
+    output: &mut impl Write,
+    bit_depth: PngBitDepth,
+    tone_mapping: &ToneMapping,
+) -> std::io::Result<()> {
+    // Encode the framebuffer as an sRGB PNG. The sRGB chunk tells viewers how the values were
+    // encoded, and the gAMA chunk does the same for decoders that don't understand sRGB
+    let mut encoder = png::Encoder::new(output, framebuffer.width as u32, framebuffer.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455));
+
+    let intensity = Interval::new(0.0, 1.0);
+    let encode = |linear: f64| intensity.clamp(colors::linear_to_srgb(linear));
+    let components = framebuffer.pixels.iter().flat_map(|&c| {
+        let c = tone_mapping.apply(c);
+        [c.x, c.y, c.z]
+    });
+
+    let data: Vec<u8> = match bit_depth {
+        PngBitDepth::Eight => {
+            encoder.set_depth(png::BitDepth::Eight);
+            components.map(|x| (255.0 * encode(x)).round() as u8).collect()
+        }
+        PngBitDepth::Sixteen => {
+            // Sixteen bit samples are stored big endian
+            encoder.set_depth(png::BitDepth::Sixteen);
+            components.flat_map(|x| ((65535.0 * encode(x)).round() as u16).to_be_bytes()).collect()
+        }
+    };
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn write_exr(framebuffer: &Framebuffer, output: &mut impl Write, precision: ExrPrecision) -> io::Result<()> {
+    // Write the linear radiance of the framebuffer as an RGB OpenEXR image
+    let channels = Vec::from(ExrChannel::rgb("", framebuffer));
+    write_exr_channels(framebuffer.width, framebuffer.height, channels, output, precision)
+}
+
+pub fn write_exr_channels(
+    width: usize,
+    height: usize,
+    channels: Vec<ExrChannel>,
+    output: &mut impl Write,
+    precision: ExrPrecision,
+) -> io::Result<()> {
+    // Write any set of named channels as a single part OpenEXR image with lossless compression
+    let mut any_channels = SmallVec::new();
+    for channel in channels {
+        if channel.samples.len() != width * height {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("channel '{}' has {} samples for a {}x{} image", channel.name, channel.samples.len(), width, height)));
+        }
+        let samples = match precision {
+            ExrPrecision::Half => FlatSamples::F16(channel.samples.into_iter().map(f16::from_f32).collect()),
+            ExrPrecision::Float => FlatSamples::F32(channel.samples),
+        };
+        any_channels.push(AnyChannel::new(channel.name.as_str(), samples));
+    }
+
+    let layer = Layer::new(
+        (width, height),
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(any_channels),
+    );
+
+    // The encoder needs to seek back to write its offset tables, so encode into memory first
+    let mut buffer = Cursor::new(Vec::new());
+    Image::from_layer(layer)
+        .write()
+        .to_buffered(&mut buffer)
+        .map_err(io::Error::other)?;
+    output.write_all(buffer.get_ref())
+}
+
+
+fn float_to_rgbe(r: f64, g: f64, b: f64) -> [u8; 4] {
+    // Store the color as three 8 bit mantissas sharing the exponent of the largest component.
+    // Negative and NaN components have no representation and are stored as zero, and anything
+    // beyond the largest representable value, infinity included, saturates to it
+    let largest = 255.0 / 256.0 * f64::powi(2.0, 127);
+    let representable = |x: f64| if x > 0.0 { f64::min(x, largest) } else { 0.0 };
+    let (r, g, b) = (representable(r), representable(g), representable(b));
+    let v = f64::max(r, f64::max(g, b));
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    let exponent = v.log2().floor() as i32 + 1;
+    let scale = 256.0 / f64::powi(2.0, exponent);
+    let mantissa = |x: f64| f64::min(x * scale, 255.0) as u8;
+    return [mantissa(r), mantissa(g), mantissa(b), (exponent + 128) as u8];
+}
+
+fn write_rle_component(output: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    // Run length encode one component of a scanline. Runs of at least three equal bytes are
+    // stored as (128 + count, value), everything else as literal spans of (count, bytes...)
+    const MIN_RUN: usize = 3;
+    let mut pos = 0;
+    while pos < data.len() {
+        // Find the next run worth encoding
+        let mut run_start = pos;
+        let mut run_len = 0;
+        while run_start < data.len() {
+            run_len = 1;
+            while run_len < 127 && run_start + run_len < data.len() && data[run_start + run_len] == data[run_start] {
+                run_len += 1;
+            }
+            if run_len >= MIN_RUN {
+                break;
+            }
+            run_start += run_len;
+        }
+
+        // Emit the literal bytes before the run
+        while pos < run_start {
+            let count = usize::min(128, run_start - pos);
+            output.write_all(&[count as u8])?;
+            output.write_all(&data[pos..pos + count])?;
+            pos += count;
+        }
+
+        if run_len >= MIN_RUN && run_start < data.len() {
+            output.write_all(&[128 + run_len as u8, data[run_start]])?;
+            pos = run_start + run_len;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_hdr(framebuffer: &Framebuffer, output: &mut impl Write) -> io::Result<()> {
+    // Write the linear radiance of the framebuffer as a Radiance RGBE (.hdr) image
+    write!(output, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n")?;
+    writeln!(output, "-Y {} +X {}", framebuffer.height, framebuffer.width)?;
+
+    for row in framebuffer.rows() {
+        let pixels: Vec<[u8; 4]> = row.iter().map(|c| float_to_rgbe(c.x, c.y, c.z)).collect();
+
+        // Run length encoding is only defined for scanlines from 8 to 32767 pixels wide
+        if !(8..=0x7fff).contains(&pixels.len()) {
+            for pixel in &pixels {
+                output.write_all(pixel)?;
+            }
+            continue;
+        }
+
+        let width = pixels.len() as u16;
+        output.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        for component in 0..4 {
+            let data: Vec<u8> = pixels.iter().map(|p| p[component]).collect();
+            write_rle_component(output, &data)?;
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::colors::Color;
+    use image::ImageFormat;
+
+    fn test_image(width: usize, height: usize) -> Framebuffer {
+        // Flat spans for the run length encoder next to noisy ones it has to store literally,
+        // with values well above 1 and exact zeros
+        let mut framebuffer = Framebuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let color = if x < width / 3 {
+                    Color::new(0.25, 0.5, 12.0)
+                } else if x < 2 * width / 3 {
+                    Color::new(((x * 7 + y * 13) % 17) as f64 / 4.0, x as f64 / width as f64, 0.0)
+                } else {
+                    Color::new(0.0, 0.0, 0.0)
+                };
+                framebuffer.set(x, y, color);
+            }
+        }
+        return framebuffer;
+    }
+
+    fn decode(bytes: &[u8], format: ImageFormat) -> Vec<[f32; 3]> {
+        let image = image::load_from_memory_with_format(bytes, format).unwrap().into_rgb32f();
+        return image.pixels().map(|p| p.0).collect();
+    }
+
+    fn assert_close(framebuffer: &Framebuffer, decoded: &[[f32; 3]], relative: f64) {
+        assert_eq!(decoded.len(), framebuffer.pixels.len());
+        for (pixel, expected) in decoded.iter().zip(&framebuffer.pixels) {
+            // Each channel is accurate relative to the largest channel of the pixel
+            let tolerance = relative * f64::max(expected.x, f64::max(expected.y, expected.z));
+            for (value, expected) in pixel.iter().zip([expected.x, expected.y, expected.z]) {
+                assert!((*value as f64 - expected).abs() <= tolerance, "{} vs {}", value, expected);
+            }
+        }
+    }
+
+    fn decode_png(bytes: &[u8]) -> (png::Info<'static>, Vec<u8>) {
+        let mut reader = png::Decoder::new(Cursor::new(bytes)).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size().unwrap()];
+        let frame = reader.next_frame(&mut data).unwrap();
+        data.truncate(frame.buffer_size());
+        return (reader.info().clone(), data);
+    }
+
+    #[test]
+    fn png_round_trips_at_both_bit_depths() {
+        // Black, white, mid grey and values outside the display range, which are clamped
+        let linear = [0.0, 1.0, 0.5, 0.18, 0.002, 2.0, -1.0, 0.75, 0.04];
+        let mut framebuffer = Framebuffer::new(3, 1);
+        for (x, c) in linear.chunks(3).enumerate() {
+            framebuffer.set(x, 0, Color::new(c[0], c[1], c[2]));
+        }
+        let encoded = |x: f64| colors::linear_to_srgb(x).clamp(0.0, 1.0);
+
+        let mut bytes = Vec::new();
+        write_png(&framebuffer, &mut bytes, PngBitDepth::Eight, &ToneMapping::default()).unwrap();
+        let (info, data) = decode_png(&bytes);
+        assert_eq!((info.width, info.height), (3, 1));
+        assert_eq!((info.color_type, info.bit_depth), (png::ColorType::Rgb, png::BitDepth::Eight));
+        let expected: Vec<u8> = linear.iter().map(|&x| (255.0 * encoded(x)).round() as u8).collect();
+        assert_eq!(data, expected);
+        assert_eq!((data[0], data[1], data[2], data[3], data[5], data[6]), (0, 255, 188, 118, 255, 0));
+
+        let mut bytes = Vec::new();
+        write_png(&framebuffer, &mut bytes, PngBitDepth::Sixteen, &ToneMapping::default()).unwrap();
+        let (info, data) = decode_png(&bytes);
+        assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+        let samples: Vec<u16> = data.chunks(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect();
+        let expected: Vec<u16> = linear.iter().map(|&x| (65535.0 * encoded(x)).round() as u16).collect();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn png_declares_srgb_and_gamma() {
+        let mut bytes = Vec::new();
+        write_png(&Framebuffer::new(2, 2), &mut bytes, PngBitDepth::Eight, &ToneMapping::default()).unwrap();
+        let (info, _) = decode_png(&bytes);
+        assert_eq!(info.srgb, Some(png::SrgbRenderingIntent::Perceptual));
+        assert_eq!(info.gama_chunk.map(|gamma| gamma.into_scaled()), Some(45455));
+    }
+
+    #[test]
+    fn hdr_round_trips_through_run_length_encoding() {
+        let framebuffer = test_image(300, 4);
+        let mut bytes = Vec::new();
+        write_hdr(&framebuffer, &mut bytes).unwrap();
+
+        // Scanlines start with the marker of the run length encoded format
+        let header_end = bytes.windows(2).position(|w| w == b"\n\n").unwrap() + 2;
+        let data = &bytes[header_end..];
+        let resolution_end = data.iter().position(|&b| b == b'\n').unwrap() + 1;
+        assert_eq!(&data[resolution_end..resolution_end + 4], &[2, 2, 1, 44]);
+        assert!(data.len() < 4 * 300 * 4);
+
+        // Mantissas have 8 bits, so values are off by at most 1/128 of the largest channel
+        assert_close(&framebuffer, &decode(&bytes, ImageFormat::Hdr), 1.0 / 128.0);
+    }
+
+    #[test]
+    fn hdr_round_trips_scanlines_too_narrow_to_encode() {
+        let framebuffer = test_image(6, 3);
+        let mut bytes = Vec::new();
+        write_hdr(&framebuffer, &mut bytes).unwrap();
+        assert_close(&framebuffer, &decode(&bytes, ImageFormat::Hdr), 1.0 / 128.0);
+    }
+
+    #[test]
+    fn hdr_saturates_values_it_cannot_represent() {
+        // Infinite depth and the -1 id of background pixels in raw AOVs must not break the file
+        let mut framebuffer = Framebuffer::new(4, 1);
+        framebuffer.set(0, 0, Color::new(f64::INFINITY, f64::INFINITY, f64::INFINITY));
+        framebuffer.set(1, 0, Color::new(-1.0, -1.0, -1.0));
+        framebuffer.set(2, 0, Color::new(f64::NAN, 2.0, 0.5));
+        framebuffer.set(3, 0, Color::new(1e300, 1.0, -0.5));
+        let mut bytes = Vec::new();
+        write_hdr(&framebuffer, &mut bytes).unwrap();
+
+        let decoded = decode(&bytes, ImageFormat::Hdr);
+        assert!(decoded[0].iter().all(|&x| x.is_finite() && x > 1e38), "{:?}", decoded[0]);
+        assert_eq!(decoded[1], [0.0, 0.0, 0.0]);
+        assert_eq!(decoded[2], [0.0, 2.0, 0.5]);
+        assert!(decoded[3][0].is_finite() && decoded[3][0] > 1e38 && decoded[3][2] == 0.0, "{:?}", decoded[3]);
+    }
+
+    #[test]
+    fn exr_round_trips_at_both_precisions() {
+        let framebuffer = test_image(40, 5);
+
+        let mut bytes = Vec::new();
+        write_exr(&framebuffer, &mut bytes, ExrPrecision::Float).unwrap();
+        assert_close(&framebuffer, &decode(&bytes, ImageFormat::OpenExr), 1e-7);
+
+        let mut bytes = Vec::new();
+        write_exr(&framebuffer, &mut bytes, ExrPrecision::Half).unwrap();
+        assert_close(&framebuffer, &decode(&bytes, ImageFormat::OpenExr), 1e-3);
+    }
+
+    #[test]
+    fn exr_rejects_channels_of_the_wrong_size() {
+        let channels = vec![ExrChannel::new("Z", vec![0.0; 5])];
+        let result = write_exr_channels(2, 3, channels, &mut Vec::new(), ExrPrecision::Float);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
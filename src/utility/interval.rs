@@ -1,12 +1,14 @@
 
-
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Copy)]
 pub struct Interval {
     pub min: f64,
     pub max: f64
 }
 
 impl Interval {
+    pub const EMPTY: Interval = Interval { min: f64::INFINITY, max: f64::NEG_INFINITY };
+    pub const UNIVERSE: Interval = Interval { min: f64::NEG_INFINITY, max: f64::INFINITY };
+
     pub fn new(min: f64, max: f64) -> Self {
         Interval  {
             min: min,
@@ -14,6 +16,14 @@ impl Interval {
         }
     }
 
+    pub fn enclosing(a: &Interval, b: &Interval) -> Self {
+        // Create the interval tightly enclosing the two input intervals
+        Interval {
+            min: f64::min(a.min, b.min),
+            max: f64::max(a.max, b.max),
+        }
+    }
+
     pub fn size(&self) -> f64 {
         return self.max - self.min;
     }
@@ -31,6 +41,11 @@ impl Interval {
         if x > self.max { return self.max;}
         return x;
     }
+
+    pub fn expand(&self, delta: f64) -> Self {
+        let padding = delta / 2.0;
+        Interval::new(self.min - padding, self.max + padding)
+    }
 }
 
 
@@ -0,0 +1,232 @@
+use crate::utility::aabb::Aabb;
+use crate::utility::common;
+use crate::utility::distribution::AliasTable;
+use crate::utility::hittable::{HitRecord, Hittable};
+use crate::utility::interval::Interval;
+use crate::utility::punctual_light::PunctualLight;
+use crate::utility::ray::Ray;
+use crate::utility::vec3::{Point3, Vec3};
+
+
+// How a light is picked for each light sample
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LightSampling {
+    Uniform, // Every light equally often
+    #[default]
+    Power, // In proportion to the power of each light
+    Bvh, // Down the light BVH, by the estimated contribution of each branch to the shading point
+}
+
+
+struct LightNode {
+    bbox: Aabb,
+    power: f64, // Total power of the lights below the node
+    offset: usize, // Leaf: index of the light. Interior: index of the second child
+    leaf: bool,
+}
+
+
+// The emitters of a scene, sampled by the camera to light each shading point directly. The
+// lights are organised in a bounding volume hierarchy, which finds the lights a direction
+// points at without testing all of them, and with LightSampling::Bvh also steers the choice of
+// light towards the bright and nearby ones. Like nodes of the main BVH, the first child of an
+// interior node directly follows it. Punctual lights can't be picked at random like the
+// emitters, and are each sampled at every shading point instead.
+pub struct LightList {
+    lights: Vec<Box<dyn Hittable>>,
+    punctual: Vec<PunctualLight>,
+    alias: Option<AliasTable>, // Picks lights by power, None without lights
+    nodes: Vec<LightNode>,
+    sampling: LightSampling,
+}
+
+impl Default for LightList {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl LightList {
+    pub fn new(lights: Vec<(Box<dyn Hittable>, f64)>) -> Self {
+        // Lights come with their power, the light they give off in total, such as the area
+        // times the luminance of the emission. Only the ratios between lights matter
+        let (lights, power): (Vec<_>, Vec<_>) = lights.into_iter().unzip();
+
+        let mut list = LightList {
+            alias: if power.is_empty() { None } else { Some(AliasTable::new(&power)) },
+            lights: lights,
+            punctual: Vec::new(),
+            nodes: Vec::new(),
+            sampling: LightSampling::default(),
+        };
+        if !list.lights.is_empty() {
+            let mut indices: Vec<usize> = (0..list.lights.len()).collect();
+            list.nodes.reserve(2 * indices.len());
+            list.build(&power, &mut indices);
+        }
+        return list;
+    }
+
+    pub fn with_sampling(mut self, sampling: LightSampling) -> Self {
+        self.sampling = sampling;
+        return self;
+    }
+
+    pub fn with_punctual_lights(mut self, lights: Vec<PunctualLight>) -> Self {
+        self.punctual = lights;
+        return self;
+    }
+
+    pub fn punctual_lights(&self) -> &[PunctualLight] {
+        return &self.punctual;
+    }
+
+    pub fn sampling(&self) -> LightSampling {
+        return self.sampling;
+    }
+
+    pub fn len(&self) -> usize {
+        // Number of emitters, not counting punctual lights
+        return self.lights.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.lights.is_empty();
+    }
+
+    fn build(&mut self, power: &[f64], indices: &mut [usize]) {
+        let node_index = self.nodes.len();
+        let bbox = indices
+            .iter()
+            .fold(Aabb::EMPTY, |bbox, &i| Aabb::surrounding(&bbox, &self.lights[i].bounding_box()));
+        self.nodes.push(LightNode {
+            bbox: bbox,
+            power: indices.iter().map(|&i| power[i]).sum(),
+            offset: indices[0],
+            leaf: indices.len() == 1,
+        });
+        if indices.len() == 1 {
+            return;
+        }
+
+        // Split at the median centroid along the axis where the centroids spread the most
+        let centroid = |i: usize| self.lights[i].bounding_box().centroid();
+        let centroid_bounds = indices
+            .iter()
+            .fold(Aabb::EMPTY, |bbox, &i| Aabb::surrounding(&bbox, &Aabb::from_points(centroid(i), centroid(i))));
+        let axis = centroid_bounds.longest_axis();
+        let mid = indices.len() / 2;
+        indices.select_nth_unstable_by(mid, |&a, &b| centroid(a)[axis].total_cmp(&centroid(b)[axis]));
+
+        let (left, right) = indices.split_at_mut(mid);
+        self.build(power, left);
+        self.nodes[node_index].offset = self.nodes.len();
+        self.build(power, right);
+    }
+
+    fn child_probabilities(&self, node_index: usize, origin: Point3) -> (f64, f64) {
+        // Chances of descending into the first and the second child of an interior node
+        let first = importance(&self.nodes[node_index + 1], origin);
+        let second = importance(&self.nodes[self.nodes[node_index].offset], origin);
+        if first + second == 0.0 {
+            return (0.5, 0.5);
+        }
+        return (first / (first + second), second / (first + second));
+    }
+
+    fn pick(&self, origin: Point3) -> usize {
+        let n = self.lights.len();
+        match self.sampling {
+            LightSampling::Uniform => ((common::random_double() * n as f64) as usize).min(n - 1),
+            LightSampling::Power => self.alias.as_ref().expect("lights are present").sample(common::random_double()),
+            LightSampling::Bvh => {
+                let mut index = 0;
+                while !self.nodes[index].leaf {
+                    let (first, _) = self.child_probabilities(index, origin);
+                    index = if common::random_double() < first { index + 1 } else { self.nodes[index].offset };
+                }
+                self.nodes[index].offset
+            }
+        }
+    }
+
+    fn node_pdf(&self, index: usize, probability: f64, ray: &Ray) -> f64 {
+        // Density of the lights below the node picking the ray direction. probability is the
+        // chance of reaching the node, which only the BVH sampling tracks
+        let node = &self.nodes[index];
+        if probability == 0.0 || !node.bbox.hit(ray, Interval::new(0.001, common::INFINITY)) {
+            return 0.0;
+        }
+
+        if node.leaf {
+            let pick = match self.sampling {
+                LightSampling::Uniform => 1.0 / self.lights.len() as f64,
+                LightSampling::Power => self.alias.as_ref().expect("lights are present").pmf(node.offset),
+                LightSampling::Bvh => probability,
+            };
+            return pick * self.lights[node.offset].pdf_value(ray.origin(), ray.direction());
+        }
+
+        let (first, second) = match self.sampling {
+            LightSampling::Bvh => self.child_probabilities(index, ray.origin()),
+            _ => (1.0, 1.0),
+        };
+        return self.node_pdf(index + 1, probability * first, ray) + self.node_pdf(node.offset, probability * second, ray);
+    }
+
+    fn node_hit(&self, index: usize, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let node = &self.nodes[index];
+        if !node.bbox.hit(r, ray_t) {
+            return None;
+        }
+
+        if node.leaf {
+            let mut rec = self.lights[node.offset].hit(r, ray_t)?;
+            rec.object_id = node.offset;
+            return Some(rec);
+        }
+
+        let first = self.node_hit(index + 1, r, ray_t);
+        let closest = first.as_ref().map_or(ray_t.max, |rec| rec.t);
+        let second = self.node_hit(node.offset, r, Interval::new(ray_t.min, closest));
+        return second.or(first);
+    }
+}
+
+
+fn importance(node: &LightNode, p: Point3) -> f64 {
+    // Estimated contribution of the lights below a node to point p: their power over the
+    // squared distance to the centre of their bounds. The distance doesn't drop below half the
+    // diagonal, so points inside or next to a large cluster don't overrate it
+    let diagonal = Vec3::new(node.bbox.x.size(), node.bbox.y.size(), node.bbox.z.size());
+    let distance_squared = (p - node.bbox.centroid()).length_squared().max(0.25 * diagonal.length_squared());
+    return node.power / distance_squared;
+}
+
+
+impl Hittable for LightList {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        return self.node_hit(0, r, ray_t);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.nodes.first().map_or(Aabb::EMPTY, |node| node.bbox);
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        if self.nodes.is_empty() {
+            return 0.0;
+        }
+        return self.node_pdf(0, 1.0, &Ray::new(origin, direction));
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        if self.lights.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        return self.lights[self.pick(origin)].random(origin);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::utility::aabb::Aabb;
+use crate::utility::hittable::{HitRecord, Hittable};
+use crate::utility::hittable_list::HittableList;
+use crate::utility::interval::Interval;
+use crate::utility::ray::Ray;
+use crate::utility::vec3::{Point3, Vec3};
+
+
+// Number of centroid bins evaluated per split when applying the surface area heuristic
+const SAH_BUCKETS: usize = 12;
+// Nodes holding this many primitives or fewer may become leaves
+const MAX_PRIMS_IN_NODE: usize = 4;
+// Cost of a node traversal step relative to one primitive intersection test
+const TRAVERSAL_COST: f64 = 0.125;
+// Depth of the traversal stack. Traversal pushes at most one node per level, so the build stops
+// splitting at this depth
+const STACK_SIZE: usize = 64;
+
+
+struct LinearBvhNode {
+    bbox: Aabb,
+    offset: usize, // Leaf: index of the first primitive. Interior: index of the second child
+    count: usize, // Number of primitives in a leaf, zero for interior nodes
+    axis: usize, // Split axis of an interior node
+}
+
+struct PrimitiveInfo {
+    index: usize,
+    bbox: Aabb,
+    centroid: Point3,
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    count: usize,
+    bbox: Aabb,
+}
+
+
+// Bounding volume hierarchy built with the surface area heuristic over binned centroids, and
+// stored as a flat array of nodes in depth-first order. The first child of an interior node
+// directly follows it, so only the offset of the second child is stored.
+pub struct LinearBvh {
+    primitives: Vec<Box<dyn Hittable>>,
+    primitive_ids: Vec<usize>, // Position of each primitive in the list the BVH was built from
+    nodes: Vec<LinearBvhNode>,
+    bbox: Aabb,
+}
+
+
+impl LinearBvh {
+    pub fn new(list: HittableList) -> Self {
+        Self::from_objects(list.objects)
+    }
+
+    pub fn from_objects(objects: Vec<Box<dyn Hittable>>) -> Self {
+        let mut info: Vec<PrimitiveInfo> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| {
+                let bbox = object.bounding_box();
+                PrimitiveInfo { index: index, bbox: bbox, centroid: bbox.centroid() }
+            })
+            .collect();
+
+        let mut bvh = LinearBvh {
+            primitives: Vec::with_capacity(objects.len()),
+            primitive_ids: Vec::new(),
+            nodes: Vec::with_capacity(2 * objects.len()),
+            bbox: Aabb::EMPTY,
+        };
+
+        let mut ordered = Vec::with_capacity(objects.len());
+        if !info.is_empty() {
+            bvh.build(&mut info, &mut ordered, 0);
+            bvh.bbox = bvh.nodes[0].bbox;
+        }
+
+        // Move the primitives into leaf order, so each leaf references a contiguous range
+        let mut slots: Vec<Option<Box<dyn Hittable>>> = objects.into_iter().map(Some).collect();
+        bvh.primitives = ordered
+            .iter()
+            .map(|&index| slots[index].take().expect("primitive referenced by two leaves"))
+            .collect();
+        bvh.primitive_ids = ordered;
+
+        return bvh;
+    }
+
+    fn build(&mut self, info: &mut [PrimitiveInfo], ordered: &mut Vec<usize>, depth: usize) -> usize {
+        let node_index = self.nodes.len();
+        let bbox = info
+            .iter()
+            .fold(Aabb::EMPTY, |bbox, prim| Aabb::surrounding(&bbox, &prim.bbox));
+        self.nodes.push(LinearBvhNode { bbox: bbox, offset: 0, count: 0, axis: 0 });
+
+        let n = info.len();
+        if n == 1 {
+            self.make_leaf(node_index, info, ordered);
+            return node_index;
+        }
+
+        // Degenerate input can make the tree arbitrarily deep. Past the depth the traversal
+        // stack can hold, keep the remaining primitives together in one large leaf
+        if depth + 1 >= STACK_SIZE {
+            self.make_leaf(node_index, info, ordered);
+            return node_index;
+        }
+
+        let centroid_bounds = info.iter().fold(Aabb::EMPTY, |bbox, prim| {
+            Aabb::surrounding(&bbox, &Aabb::from_points(prim.centroid, prim.centroid))
+        });
+        let axis = centroid_bounds.longest_axis();
+        let axis_bounds = *centroid_bounds.axis_interval(axis);
+
+        // Every centroid coincides, so no split can separate the primitives
+        if axis_bounds.size() <= 0.0 {
+            self.make_leaf(node_index, info, ordered);
+            return node_index;
+        }
+
+        let mid = if n <= 2 {
+            // Too few primitives for the heuristic to matter, split them evenly
+            info.select_nth_unstable_by(n / 2, |a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+            n / 2
+        } else {
+            let bucket_of = |prim: &PrimitiveInfo| -> usize {
+                let b = (SAH_BUCKETS as f64 * (prim.centroid[axis] - axis_bounds.min) / axis_bounds.size()) as usize;
+                usize::min(b, SAH_BUCKETS - 1)
+            };
+
+            let mut buckets = [Bucket { count: 0, bbox: Aabb::EMPTY }; SAH_BUCKETS];
+            for prim in info.iter() {
+                let b = bucket_of(prim);
+                buckets[b].count += 1;
+                buckets[b].bbox = Aabb::surrounding(&buckets[b].bbox, &prim.bbox);
+            }
+
+            // Estimate the cost of splitting after each bucket, sweeping from both ends so
+            // every candidate is evaluated in linear time
+            let mut costs = [0.0; SAH_BUCKETS - 1];
+            let mut count_below = 0;
+            let mut bbox_below = Aabb::EMPTY;
+            for i in 0..SAH_BUCKETS - 1 {
+                count_below += buckets[i].count;
+                bbox_below = Aabb::surrounding(&bbox_below, &buckets[i].bbox);
+                costs[i] = count_below as f64 * bbox_below.surface_area();
+            }
+            let mut count_above = 0;
+            let mut bbox_above = Aabb::EMPTY;
+            for i in (1..SAH_BUCKETS).rev() {
+                count_above += buckets[i].count;
+                bbox_above = Aabb::surrounding(&bbox_above, &buckets[i].bbox);
+                costs[i - 1] += count_above as f64 * bbox_above.surface_area();
+            }
+
+            let (min_bucket, min_cost) = costs
+                .iter()
+                .enumerate()
+                .fold((0, f64::INFINITY), |best, (i, &c)| if c < best.1 { (i, c) } else { best });
+
+            let area = bbox.surface_area();
+            let split_cost = if area > 0.0 { TRAVERSAL_COST + min_cost / area } else { n as f64 };
+            let leaf_cost = n as f64;
+
+            if n <= MAX_PRIMS_IN_NODE && leaf_cost <= split_cost {
+                self.make_leaf(node_index, info, ordered);
+                return node_index;
+            }
+
+            let mid = partition(info, |prim| bucket_of(prim) <= min_bucket);
+            if mid == 0 || mid == n {
+                // The heuristic put everything on one side, fall back to an even split
+                info.select_nth_unstable_by(n / 2, |a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+                n / 2
+            } else {
+                mid
+            }
+        };
+
+        let (left, right) = info.split_at_mut(mid);
+        self.build(left, ordered, depth + 1);
+        let second_child = self.build(right, ordered, depth + 1);
+
+        let node = &mut self.nodes[node_index];
+        node.offset = second_child;
+        node.axis = axis;
+        return node_index;
+    }
+
+    fn make_leaf(&mut self, node_index: usize, info: &[PrimitiveInfo], ordered: &mut Vec<usize>) {
+        let node = &mut self.nodes[node_index];
+        node.offset = ordered.len();
+        node.count = info.len();
+        ordered.extend(info.iter().map(|prim| prim.index));
+    }
+}
+
+
+fn partition<T>(items: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    // Reorder the items so those matching the predicate come first, and return how many matched
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    return mid;
+}
+
+
+impl Hittable for LinearBvh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let origin = r.origin();
+        let dir = r.direction();
+        let inv_dir = Vec3::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+        let mut temp_rec = None;
+        let mut closest_so_far = ray_t.max;
+
+        let mut stack = [0usize; STACK_SIZE];
+        let mut stack_len = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            if node.bbox.hit_precomputed(&origin, &inv_dir, Interval::new(ray_t.min, closest_so_far)) {
+                if node.count > 0 {
+                    for index in node.offset..node.offset + node.count {
+                        if let Some(mut rec) = self.primitives[index].hit(r, Interval::new(ray_t.min, closest_so_far)) {
+                            closest_so_far = rec.t;
+                            rec.object_id = self.primitive_ids[index];
+                            temp_rec = Some(rec);
+                        }
+                    }
+                } else {
+                    // Visit the child nearer to the ray origin first, so closer hits shrink the
+                    // interval before the far child is tested
+                    let (near, far) = if dir_is_neg[node.axis] {
+                        (node.offset, current + 1)
+                    } else {
+                        (current + 1, node.offset)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    current = near;
+                    continue;
+                }
+            }
+
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            current = stack[stack_len];
+        }
+
+        return temp_rec;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::colors::Color;
+    use crate::utility::material::Lambertian;
+    use crate::utility::sphere::Sphere;
+    use std::sync::Arc;
+
+    #[test]
+    fn degenerate_input_stays_within_the_traversal_stack() {
+        // Centroids that shrink geometrically leave a single primitive in the top SAH bucket
+        // at every level, which would otherwise build a tree far deeper than the stack
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let make_list = || {
+            let mut list = HittableList::new();
+            for i in 0..100 {
+                let x = 1e-3f64.powi(i);
+                list.add(Box::new(Sphere::new(Point3::new(x, 0.0, 0.0), 0.1 * x, material.clone())));
+            }
+            list
+        };
+        let list = make_list();
+        let bvh = LinearBvh::new(make_list());
+
+        // A ray along the axis passes through every node down to the smallest spheres
+        let ray = Ray::new(Point3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let expected = list.hit(&ray, ray_t).expect("ray along the spheres");
+        let rec = bvh.hit(&ray, ray_t).expect("ray along the spheres");
+        // The smallest spheres are all hit at the same t in double precision, so compare the
+        // distance rather than which of them was reported
+        assert_eq!(rec.t, expected.t);
+    }
+}
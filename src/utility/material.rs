@@ -3,11 +3,21 @@ use crate::utility::colors::Color;
 use crate::utility::ray::Ray;
 use crate::utility::vec3;
 use crate::utility::common;
+use crate::utility::pdf::{CosinePdf, Pdf};
+use crate::utility::texture::{SolidColor, Texture};
+use crate::utility::vec3::Point3;
 
+use std::sync::Arc;
+
+
+pub enum Scattered {
+    Specular(Ray), // A single outgoing ray, as from mirrors and glass
+    Pdf(Box<dyn Pdf>), // Outgoing directions spread over a density
+}
 
 pub struct ScatterRecord {
     pub attenuation: Color,
-    pub scattered: Ray,
+    pub scattered: Scattered,
 }
 
 pub trait Material: Send + Sync {
@@ -16,16 +26,45 @@ pub trait Material: Send + Sync {
         r_in: &Ray,
         rec: &HitRecord,
         ) -> Option<ScatterRecord>;
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        // Overall reflectance at the hit, as used for albedo output. White unless the material
+        // tints what it scatters
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        // Distribution of light scattered into a direction, normalized so that attenuation
+        // times this value is the BSDF times the cosine term. Only used by materials that
+        // scatter through a pdf
+        0.0
+    }
+
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        // Light given off by the surface, none unless the material is a light source
+        Color::new(0.0, 0.0, 0.0)
+    }
 }
 
 
 pub struct Lambertian {
-    albedo: Color,
+    tex: Arc<dyn Texture>,
+    vertex_colors: bool, // Take the albedo from the hit's vertex color when it has one
 }
 
 impl Lambertian {
     pub fn new(c: Color) -> Self {
-        Lambertian { albedo: c }
+        Lambertian::from_texture(Arc::new(SolidColor::new(c)))
+    }
+
+    pub fn from_texture(tex: Arc<dyn Texture>) -> Self {
+        Lambertian { tex: tex, vertex_colors: false }
+    }
+
+    pub fn vertex_colored(fallback: Color) -> Self {
+        // Use the interpolated vertex colors of meshes as albedo, and the fallback color for
+        // surfaces that don't have any
+        Lambertian { tex: Arc::new(SolidColor::new(fallback)), vertex_colors: true }
     }
 }
 
@@ -37,30 +76,39 @@ impl Material for Lambertian {
             rec: &HitRecord,
             ) -> Option<ScatterRecord> {
 
-            let mut scatter_direction = rec.normal + vec3::random_unit_vector();
-
-            // Catch degenerate scatter direction 
-            if scatter_direction.near_zero() {
-                scatter_direction = rec.normal;
-            }
-
             Some(ScatterRecord {
-                attenuation: self.albedo,
-                scattered: Ray::new(rec.p, scatter_direction),
+                attenuation: self.albedo(rec),
+                scattered: Scattered::Pdf(Box::new(CosinePdf::new(rec.normal))),
             })
 
     }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cos_theta = vec3::dot(rec.normal, vec3::unit_vector(scattered.direction()));
+        return if cos_theta < 0.0 { 0.0 } else { cos_theta / common::PI };
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        match rec.color {
+            Some(color) if self.vertex_colors => color,
+            _ => self.tex.value(rec.u, rec.v, &rec.p),
+        }
+    }
 }
 
 
 pub struct Metal {
-    albedo: Color,
+    tex: Arc<dyn Texture>,
     fuzz: f64,
 }
 
 impl Metal {
     pub fn new(c: Color, f: f64) -> Self {
-        Metal { albedo: c, 
+        Metal::from_texture(Arc::new(SolidColor::new(c)), f)
+    }
+
+    pub fn from_texture(tex: Arc<dyn Texture>, f: f64) -> Self {
+        Metal { tex: tex,
                 fuzz: if f < 1.0 {f} else {1.0}, }
     }
 }
@@ -74,16 +122,21 @@ impl Material for Metal {
             ) -> Option<ScatterRecord> {
 
             let reflected = vec3::reflect(r_in.direction(), rec.normal);
-            let scattered = Ray::new(rec.p, reflected + self.fuzz * vec3::random_unit_vector());
+            let reflected = vec3::unit_vector(reflected) + (self.fuzz * vec3::random_unit_vector());
+            let scattered = Ray::new(rec.p, reflected);
             if vec3::dot(scattered.direction(), rec.normal) > 0.0 {
                 Some(ScatterRecord {
-                    attenuation: self.albedo,
-                    scattered: Ray::new(rec.p, reflected),
+                    attenuation: self.albedo(rec),
+                    scattered: Scattered::Specular(scattered),
                 })
             } else {
                 None
             }
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.tex.value(rec.u, rec.v, &rec.p)
+    }
 }
 
 
@@ -133,8 +186,38 @@ impl Material for Dielectric {
 
             Some(ScatterRecord {
                 attenuation: Color::new(1.0,1.0,1.0),
-                scattered: Ray::new(rec.p, direction),
+                scattered: Scattered::Specular(Ray::new(rec.p, direction)),
             })
     }
 }
 
+
+
+pub struct DiffuseLight {
+    tex: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        DiffuseLight::from_texture(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub fn from_texture(tex: Arc<dyn Texture>) -> Self {
+        DiffuseLight { tex: tex }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+            &self,
+            _r_in: &Ray,
+            _rec: &HitRecord,
+            ) -> Option<ScatterRecord> {
+            // Lights only emit, they don't reflect anything
+            None
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.tex.value(u, v, p)
+    }
+}
@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::utility::colors::Color;
+use crate::utility::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::utility::triangle::{MeshError, TriangleMesh};
+use crate::utility::vec3::{Point3, Vec3};
+
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, line: usize, message: String },
+    Mesh { path: PathBuf, source: MeshError },
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Mesh { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+            ObjError::Mesh { source, .. } => Some(source),
+        }
+    }
+}
+
+
+// Material parameters as written in an MTL file, before they are mapped onto a renderer material
+struct MtlMaterial {
+    diffuse: Color, // Kd
+    specular: Color, // Ks
+    emissive: Color, // Ke
+    transmission: Color, // Tf
+    shininess: f64, // Ns
+    dissolve: f64, // d, or 1 - Tr
+    optical_density: f64, // Ni
+    illum: i32,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Color::from_float(0.8),
+            specular: Color::from_float(0.0),
+            emissive: Color::from_float(0.0),
+            transmission: Color::from_float(0.0),
+            shininess: 0.0,
+            dissolve: 1.0,
+            optical_density: 1.5,
+            illum: 2,
+        }
+    }
+}
+
+impl MtlMaterial {
+    fn to_material(&self) -> Arc<dyn Material> {
+        // Pick whichever renderer material best matches the dominant term of the MTL model.
+        // Any emission makes the surface a light, since lights don't reflect
+        if max_component(self.emissive) > 0.0 {
+            return Arc::new(DiffuseLight::new(self.emissive));
+        }
+
+        let transparent = self.dissolve < 1.0
+            || max_component(self.transmission) > 0.0
+            || matches!(self.illum, 4 | 6 | 7 | 9);
+        if transparent {
+            return Arc::new(Dielectric::new(self.optical_density));
+        }
+
+        let mirror = matches!(self.illum, 3 | 5);
+        if mirror || max_component(self.specular) > max_component(self.diffuse) {
+            // Map the Phong exponent onto fuzz, so low exponents give blurry reflections.
+            // Exporters often leave Ks at zero for mirrors, which would make them black, so
+            // those reflect in the diffuse color instead
+            let fuzz = f64::sqrt(2.0 / (self.shininess + 2.0));
+            let color = if max_component(self.specular) > 0.0 { self.specular } else { self.diffuse };
+            return Arc::new(Metal::new(color, fuzz));
+        }
+
+        return Arc::new(Lambertian::new(self.diffuse));
+    }
+}
+
+fn max_component(c: Color) -> f64 {
+    return f64::max(c.x, f64::max(c.y, c.z));
+}
+
+
+// Tracks the file and line being parsed so every error can point back at its source
+struct LineContext<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl LineContext<'_> {
+    fn error(&self, message: impl Into<String>) -> ObjError {
+        ObjError::Parse { path: self.path.to_path_buf(), line: self.line, message: message.into() }
+    }
+
+    fn parse_f64(&self, token: Option<&str>, what: &str) -> Result<f64, ObjError> {
+        let token = token.ok_or_else(|| self.error(format!("missing {}", what)))?;
+        token.parse::<f64>().map_err(|_| self.error(format!("invalid {} '{}'", what, token)))
+    }
+
+    fn parse_vec3(&self, tokens: &mut std::str::SplitWhitespace, what: &str) -> Result<Vec3, ObjError> {
+        let x = self.parse_f64(tokens.next(), what)?;
+        let y = self.parse_f64(tokens.next(), what)?;
+        let z = self.parse_f64(tokens.next(), what)?;
+        return Ok(Vec3::new(x, y, z));
+    }
+
+    fn parse_color(&self, tokens: &mut std::str::SplitWhitespace, what: &str) -> Result<Color, ObjError> {
+        // MTL allows a single value as shorthand for a grey color
+        let r = self.parse_f64(tokens.next(), what)?;
+        match tokens.next() {
+            None => Ok(Color::from_float(r)),
+            g => {
+                let g = self.parse_f64(g, what)?;
+                let b = self.parse_f64(tokens.next(), what)?;
+                Ok(Color::new(r, g, b))
+            }
+        }
+    }
+
+    fn resolve_index(&self, token: &str, count: usize, what: &str) -> Result<usize, ObjError> {
+        // OBJ indices are 1-based, negative values count back from the latest element
+        let index: i64 = token.parse().map_err(|_| self.error(format!("invalid {} index '{}'", what, token)))?;
+        let resolved = if index > 0 { index - 1 } else { count as i64 + index };
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(format!("{} index {} out of range ({} defined)", what, index, count)));
+        }
+        return Ok(resolved as usize);
+    }
+}
+
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|e| ObjError::Io { path: path.to_path_buf(), source: e })
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+fn load_mtl(path: &Path, materials: &mut HashMap<String, MtlMaterial>) -> Result<(), ObjError> {
+    let source = read_file(path)?;
+    let mut current: Option<String> = None;
+
+    for (i, raw_line) in source.lines().enumerate() {
+        let ctx = LineContext { path: path, line: i + 1 };
+        let mut tokens = strip_comment(raw_line).split_whitespace();
+        let Some(keyword) = tokens.next() else { continue };
+
+        if keyword == "newmtl" {
+            let name = tokens.next().ok_or_else(|| ctx.error("newmtl without a name"))?;
+            materials.insert(name.to_string(), MtlMaterial::default());
+            current = Some(name.to_string());
+            continue;
+        }
+
+        let Some(mat) = current.as_ref().and_then(|name| materials.get_mut(name)) else {
+            return Err(ctx.error(format!("'{}' before any newmtl", keyword)));
+        };
+
+        match keyword {
+            "Kd" => mat.diffuse = ctx.parse_color(&mut tokens, "diffuse color")?,
+            "Ks" => mat.specular = ctx.parse_color(&mut tokens, "specular color")?,
+            "Ke" => mat.emissive = ctx.parse_color(&mut tokens, "emissive color")?,
+            "Tf" => mat.transmission = ctx.parse_color(&mut tokens, "transmission filter")?,
+            "Ns" => mat.shininess = ctx.parse_f64(tokens.next(), "specular exponent")?,
+            "Ni" => mat.optical_density = ctx.parse_f64(tokens.next(), "optical density")?,
+            "d" => mat.dissolve = ctx.parse_f64(tokens.next(), "dissolve")?,
+            "Tr" => mat.dissolve = 1.0 - ctx.parse_f64(tokens.next(), "transparency")?,
+            "illum" => {
+                let token = tokens.next().ok_or_else(|| ctx.error("missing illumination model"))?;
+                mat.illum = token.parse().map_err(|_| ctx.error(format!("invalid illumination model '{}'", token)))?;
+            }
+            // Texture maps and the remaining statements have no renderer equivalent
+            _ => {}
+        }
+    }
+    return Ok(());
+}
+
+
+// Triangles gathered for one object or group and material, with OBJ's separate position,
+// texture and normal indices merged into shared mesh vertices
+#[derive(Default)]
+struct MeshBuilder {
+    material: Option<String>,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    indices: Vec<[usize; 3]>,
+    vertex_map: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    missing_normals: bool,
+    missing_uvs: bool,
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, key: (usize, Option<usize>, Option<usize>), obj: &ObjData) -> usize {
+        if let Some(&index) = self.vertex_map.get(&key) {
+            return index;
+        }
+        let (p, t, n) = key;
+        let index = self.positions.len();
+        self.positions.push(obj.positions[p]);
+        match n {
+            Some(n) => self.normals.push(obj.normals[n]),
+            None => { self.missing_normals = true; self.normals.push(Vec3::default()); }
+        }
+        match t {
+            Some(t) => self.uvs.push(obj.uvs[t]),
+            None => { self.missing_uvs = true; self.uvs.push((0.0, 0.0)); }
+        }
+        self.vertex_map.insert(key, index);
+        return index;
+    }
+
+    fn build(mut self, materials: &HashMap<String, Arc<dyn Material>>, default: &Arc<dyn Material>) -> Option<Result<TriangleMesh, MeshError>> {
+        if self.indices.is_empty() {
+            return None;
+        }
+        // Vertex attributes only make sense if every vertex of the mesh provides them
+        if self.missing_normals { self.normals.clear(); }
+        if self.missing_uvs { self.uvs.clear(); }
+
+        let material = self.material
+            .as_ref()
+            .and_then(|name| materials.get(name))
+            .unwrap_or(default)
+            .clone();
+        return Some(TriangleMesh::new(self.positions, self.normals, self.uvs, self.indices, material));
+    }
+}
+
+pub struct ObjScene {
+    pub meshes: Vec<TriangleMesh>, // One per object, group or material change
+    pub warnings: Vec<String>, // Problems that were worked around, such as missing materials
+}
+
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+}
+
+
+pub fn load_obj(path: impl AsRef<Path>) -> Result<ObjScene, ObjError> {
+    // Load a Wavefront OBJ file and the MTL libraries it references. Every object, group or
+    // material change starts a new triangle mesh. Missing material libraries and unknown
+    // material names fall back to a default material, with a warning
+    let path = path.as_ref();
+    let source = read_file(path)?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut warnings = Vec::new();
+    let mut obj = ObjData::default();
+    let mut mtl_materials: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut builders: Vec<MeshBuilder> = vec![MeshBuilder::default()];
+
+    for (i, raw_line) in source.lines().enumerate() {
+        let ctx = LineContext { path: path, line: i + 1 };
+        let mut tokens = strip_comment(raw_line).split_whitespace();
+        let Some(keyword) = tokens.next() else { continue };
+
+        match keyword {
+            "v" => obj.positions.push(ctx.parse_vec3(&mut tokens, "vertex coordinate")?),
+            "vn" => obj.normals.push(ctx.parse_vec3(&mut tokens, "normal coordinate")?),
+            "vt" => {
+                let u = ctx.parse_f64(tokens.next(), "texture coordinate")?;
+                let v = match tokens.next() {
+                    Some(v) => ctx.parse_f64(Some(v), "texture coordinate")?,
+                    None => 0.0,
+                };
+                obj.uvs.push((u, v));
+            }
+            "f" => {
+                let mut face = Vec::new();
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let p = ctx.resolve_index(parts.next().unwrap_or(""), obj.positions.len(), "vertex")?;
+                    let t = match parts.next() {
+                        Some(t) if !t.is_empty() => Some(ctx.resolve_index(t, obj.uvs.len(), "texture coordinate")?),
+                        _ => None,
+                    };
+                    let n = match parts.next() {
+                        Some(n) if !n.is_empty() => Some(ctx.resolve_index(n, obj.normals.len(), "normal")?),
+                        _ => None,
+                    };
+                    if parts.next().is_some() {
+                        return Err(ctx.error(format!("malformed face vertex '{}'", token)));
+                    }
+                    face.push((p, t, n));
+                }
+                if face.len() < 3 {
+                    return Err(ctx.error(format!("face needs at least 3 vertices, found {}", face.len())));
+                }
+
+                // Triangulate polygons as a fan around the first vertex
+                let builder = builders.last_mut().unwrap();
+                let first = builder.vertex(face[0], &obj);
+                for k in 1..face.len() - 1 {
+                    let b = builder.vertex(face[k], &obj);
+                    let c = builder.vertex(face[k + 1], &obj);
+                    builder.indices.push([first, b, c]);
+                }
+            }
+            "o" | "g" => {
+                let material = builders.last().unwrap().material.clone();
+                builders.push(MeshBuilder { material: material, ..Default::default() });
+            }
+            "usemtl" => {
+                let name = tokens.next().ok_or_else(|| ctx.error("usemtl without a material name"))?;
+                if !mtl_materials.contains_key(name) {
+                    warnings.push(format!("{}:{}: unknown material '{}', using the default material", path.display(), ctx.line, name));
+                }
+                builders.push(MeshBuilder { material: Some(name.to_string()), ..Default::default() });
+            }
+            "mtllib" => {
+                let names: Vec<&str> = tokens.collect();
+                if names.is_empty() {
+                    return Err(ctx.error("mtllib without a file name"));
+                }
+                for name in names {
+                    match load_mtl(&base_dir.join(name), &mut mtl_materials) {
+                        // Assets often ship without their material library, so only errors
+                        // inside a library that does exist stop the import
+                        Err(ObjError::Io { path: mtl_path, source }) => {
+                            warnings.push(format!("{}: {}, using the default material", mtl_path.display(), source));
+                        }
+                        result => result?,
+                    }
+                }
+            }
+            // Smoothing groups, lines, points and free-form geometry are not rendered
+            _ => {}
+        }
+    }
+
+    let materials: HashMap<String, Arc<dyn Material>> = mtl_materials
+        .iter()
+        .map(|(name, mtl)| (name.clone(), mtl.to_material()))
+        .collect();
+    let default_material: Arc<dyn Material> = Arc::new(Lambertian::new(Color::from_float(0.8)));
+
+    let meshes = builders
+        .into_iter()
+        .filter_map(|builder| builder.build(&materials, &default_material))
+        .collect::<Result<_, _>>()
+        .map_err(|e| ObjError::Mesh { path: path.to_path_buf(), source: e })?;
+    return Ok(ObjScene { meshes: meshes, warnings: warnings });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::hittable::Hittable;
+    use crate::utility::interval::Interval;
+    use crate::utility::material::Scattered;
+    use crate::utility::ray::Ray;
+
+    fn write_obj(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        // Write the files into a fresh directory and return the path of the first one
+        let dir = std::env::temp_dir().join(format!("obj_test_{}_{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        return dir.join(files[0].0);
+    }
+
+    fn hits(mesh: &TriangleMesh, x: f64, y: f64) -> bool {
+        let ray = Ray::new(Point3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        return mesh.hit(&ray, Interval::new(0.001, f64::INFINITY)).is_some();
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_a_fan() {
+        let path = write_obj("fan", &[("pentagon.obj", "\
+v 0 0 0
+v 2 0 0
+v 3 1 0
+v 1 2 0
+v -1 1 0
+f 1 2 3 4 5
+")]);
+        let scene = load_obj(&path).unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].triangle_count(), 3);
+        assert!(scene.warnings.is_empty());
+
+        // Points in each of the three fan triangles, and one outside the pentagon
+        assert!(hits(&scene.meshes[0], 1.5, 0.2));
+        assert!(hits(&scene.meshes[0], 1.5, 1.2));
+        assert!(hits(&scene.meshes[0], 0.0, 1.0));
+        assert!(!hits(&scene.meshes[0], 3.0, 2.0));
+    }
+
+    #[test]
+    fn parse_errors_carry_the_line_number() {
+        let path = write_obj("bad_index", &[("bad.obj", "v 0 0 0\nv 1 0 0\n\n# comment\nf 1 2 9\n")]);
+        match load_obj(&path) {
+            Err(ObjError::Parse { line, message, .. }) => {
+                assert_eq!(line, 5);
+                assert!(message.contains("out of range"), "{}", message);
+            }
+            _ => panic!("expected a parse error"),
+        }
+
+        let path = write_obj("bad_number", &[("bad.obj", "v 0 0 0\nv 1 x 0\n")]);
+        assert!(matches!(load_obj(&path), Err(ObjError::Parse { line: 2, .. })));
+
+        let path = write_obj("bad_face", &[("bad.obj", "v 0 0 0\nv 1 0 0\nf 1 2\n")]);
+        assert!(matches!(load_obj(&path), Err(ObjError::Parse { line: 3, .. })));
+    }
+
+    #[test]
+    fn errors_in_material_libraries_point_at_the_mtl_file() {
+        let path = write_obj("bad_mtl", &[
+            ("model.obj", "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n"),
+            ("model.mtl", "newmtl red\nKd 1 zero 0\n"),
+        ]);
+        match load_obj(&path) {
+            Err(ObjError::Parse { path, line, .. }) => {
+                assert!(path.ends_with("model.mtl"));
+                assert_eq!(line, 2);
+            }
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn mirrors_without_a_specular_color_reflect_the_diffuse_color() {
+        // One triangle per material, side by side along x
+        let path = write_obj("mirror", &[
+            ("model.obj", "\
+mtllib model.mtl
+v 0 0 0\nv 1 0 0\nv 0 1 0
+v 2 0 0\nv 3 0 0\nv 2 1 0
+v 4 0 0\nv 5 0 0\nv 4 1 0
+usemtl mirror\nf 1 2 3
+usemtl tinted\nf 4 5 6
+usemtl matte\nf 7 8 9
+"),
+            ("model.mtl", "\
+newmtl mirror\nillum 3\nKd 0.9 0.6 0.3\nNs 1000
+newmtl tinted\nillum 5\nKd 0.9 0.6 0.3\nKs 0.2 0.4 0.6\nNs 1000
+newmtl matte\nillum 2\nKd 0.9 0.6 0.3
+"),
+        ]);
+        let scene = load_obj(&path).unwrap();
+        assert_eq!(scene.meshes.len(), 3);
+
+        let scatter = |mesh: &TriangleMesh, x: f64| {
+            let ray = Ray::new(Point3::new(x, 0.2, 1.0), Vec3::new(0.0, 0.0, -1.0));
+            let rec = mesh.hit(&ray, Interval::new(0.001, f64::INFINITY)).unwrap();
+            return rec.mat.scatter(&ray, &rec).unwrap();
+        };
+        let close = |a: Color, b: Color| (a - b).length() < 1e-12;
+
+        let mirror = scatter(&scene.meshes[0], 0.2);
+        assert!(matches!(mirror.scattered, Scattered::Specular(_)));
+        assert!(close(mirror.attenuation, Color::new(0.9, 0.6, 0.3)));
+
+        let tinted = scatter(&scene.meshes[1], 2.2);
+        assert!(matches!(tinted.scattered, Scattered::Specular(_)));
+        assert!(close(tinted.attenuation, Color::new(0.2, 0.4, 0.6)));
+
+        let matte = scatter(&scene.meshes[2], 4.2);
+        assert!(matches!(matte.scattered, Scattered::Pdf(_)));
+    }
+
+    #[test]
+    fn missing_materials_fall_back_with_a_warning() {
+        let path = write_obj("missing_mtl", &[(
+            "model.obj",
+            "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl nowhere\nf 1 2 3\n",
+        )]);
+        let scene = load_obj(&path).unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.warnings.len(), 2);
+        assert!(scene.warnings[0].contains("missing.mtl"));
+        assert!(scene.warnings[1].contains("unknown material 'nowhere'"));
+    }
+}
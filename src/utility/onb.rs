@@ -0,0 +1,34 @@
+use crate::utility::vec3::{self, Vec3};
+
+
+// Orthonormal basis with w along a given direction
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+impl Onb {
+    pub fn new(n: Vec3) -> Self {
+        let w = vec3::unit_vector(n);
+        let a = if w.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let v = vec3::unit_vector(vec3::cross(w, a));
+        let u = vec3::cross(w, v);
+        Onb { axis: [u, v, w] }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        return self.axis[0];
+    }
+
+    pub fn v(&self) -> Vec3 {
+        return self.axis[1];
+    }
+
+    pub fn w(&self) -> Vec3 {
+        return self.axis[2];
+    }
+
+    pub fn transform(&self, v: Vec3) -> Vec3 {
+        // Transform from basis coordinates to local space
+        return (v.x * self.axis[0]) + (v.y * self.axis[1]) + (v.z * self.axis[2]);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::utility::common;
+use crate::utility::hittable::Hittable;
+use crate::utility::onb::Onb;
+use crate::utility::vec3::{self, Point3, Vec3};
+
+
+// A probability density over directions, which can both be sampled and evaluated
+pub trait Pdf {
+    fn value(&self, direction: Vec3) -> f64;
+
+    fn generate(&self) -> Vec3;
+}
+
+
+pub struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(w: Vec3) -> Self {
+        CosinePdf { uvw: Onb::new(w) }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let cosine_theta = vec3::dot(vec3::unit_vector(direction), self.uvw.w());
+        return f64::max(0.0, cosine_theta / common::PI);
+    }
+
+    fn generate(&self) -> Vec3 {
+        return self.uvw.transform(vec3::random_cosine_direction());
+    }
+}
+
+
+// Directions from an origin towards the objects, as sampled by the objects themselves
+pub struct HittablePdf<'a> {
+    objects: &'a dyn Hittable,
+    origin: Point3,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(objects: &'a dyn Hittable, origin: Point3) -> Self {
+        HittablePdf { objects: objects, origin: origin }
+    }
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: Vec3) -> f64 {
+        return self.objects.pdf_value(self.origin, direction);
+    }
+
+    fn generate(&self) -> Vec3 {
+        return self.objects.random(self.origin);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::colors::Color;
+    use crate::utility::hittable_list::HittableList;
+    use crate::utility::material::Lambertian;
+    use crate::utility::quad::Quad;
+    use crate::utility::sphere::Sphere;
+    use std::sync::Arc;
+
+    fn integrate_over_sphere(pdf: &dyn Pdf) -> f64 {
+        // Midpoint rule over the azimuth and the cosine of the polar angle, which is uniform in
+        // solid angle
+        let n = 1000;
+        let mut sum = 0.0;
+        for j in 0..n {
+            let cos_theta = 1.0 - 2.0 * (j as f64 + 0.5) / n as f64;
+            let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
+            for i in 0..n {
+                let phi = 2.0 * common::PI * (i as f64 + 0.5) / n as f64;
+                sum += pdf.value(Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+            }
+        }
+        return sum * 4.0 * common::PI / (n * n) as f64;
+    }
+
+    fn assert_generates_where_positive(pdf: &dyn Pdf) {
+        common::seed_rng(5);
+        for _ in 0..1000 {
+            let direction = pdf.generate();
+            assert!(pdf.value(direction) > 0.0, "{:?}", direction);
+        }
+    }
+
+    #[test]
+    fn cosine_pdf_integrates_to_one() {
+        let pdf = CosinePdf::new(Vec3::new(1.0, -2.0, 0.5));
+        let integral = integrate_over_sphere(&pdf);
+        assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+        assert_generates_where_positive(&pdf);
+    }
+
+    #[test]
+    fn cosine_pdf_generates_the_expected_mean_cosine() {
+        // The cosine to the normal averages 2/3 under a density proportional to it
+        common::seed_rng(9);
+        let normal = vec3::unit_vector(Vec3::new(0.0, 1.0, 1.0));
+        let pdf = CosinePdf::new(normal);
+        let samples = 20000;
+        let mean = (0..samples).map(|_| vec3::dot(vec3::unit_vector(pdf.generate()), normal)).sum::<f64>() / samples as f64;
+        assert!((mean - 2.0 / 3.0).abs() < 1e-2, "{}", mean);
+    }
+
+    #[test]
+    fn quad_pdf_integrates_to_one() {
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let quad = Quad::new(Point3::new(-1.0, 0.5, -2.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 1.5, 0.5), material);
+        let pdf = HittablePdf::new(&quad, Point3::new(0.0, 0.0, 0.0));
+        let integral = integrate_over_sphere(&pdf);
+        assert!((integral - 1.0).abs() < 1e-2, "{}", integral);
+        assert_generates_where_positive(&pdf);
+    }
+
+    #[test]
+    fn sphere_pdf_integrates_to_one() {
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, -3.0), 1.5, material);
+        let pdf = HittablePdf::new(&sphere, Point3::new(0.0, 0.0, 0.0));
+        let integral = integrate_over_sphere(&pdf);
+        assert!((integral - 1.0).abs() < 1e-2, "{}", integral);
+        assert_generates_where_positive(&pdf);
+    }
+
+    #[test]
+    fn empty_list_has_zero_density() {
+        let list = HittableList::new();
+        let pdf = HittablePdf::new(&list, Point3::new(0.0, 0.0, 0.0));
+        let direction = pdf.generate();
+        assert!(direction.length() > 0.0);
+        assert_eq!(pdf.value(direction), 0.0);
+    }
+}
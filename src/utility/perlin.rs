@@ -0,0 +1,164 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::utility::vec3::{self, Point3, Vec3};
+
+
+const POINT_COUNT: usize = 256;
+
+
+// Gradient noise over 3D space. The gradients and permutations come from their own generator,
+// so the same seed always gives the same noise field, independent of the render seed
+pub struct Perlin {
+    randvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let randvec = (0..POINT_COUNT)
+            .map(|_| loop {
+                // Rejection sample the unit ball so the gradient directions are uniform
+                let v = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                let lensq = v.length_squared();
+                if 1e-160 < lensq && lensq <= 1.0 {
+                    break v / lensq.sqrt();
+                }
+            })
+            .collect();
+        let perm_x = Self::perlin_generate_perm(&mut rng);
+        let perm_y = Self::perlin_generate_perm(&mut rng);
+        let perm_z = Self::perlin_generate_perm(&mut rng);
+
+        Perlin { randvec: randvec, perm_x: perm_x, perm_y: perm_y, perm_z: perm_z }
+    }
+
+    pub fn noise(&self, p: &Point3) -> f64 {
+        // Signed noise, roughly in [-1,1], that is zero on the integer lattice
+        let u = p.x - p.x.floor();
+        let v = p.y - p.y.floor();
+        let w = p.z - p.z.floor();
+
+        let i = p.x.floor() as i64;
+        let j = p.y.floor() as i64;
+        let k = p.z.floor() as i64;
+        let mut c = [[[Vec3::default(); 2]; 2]; 2];
+
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    *corner = self.randvec[
+                        self.perm_x[((i + di as i64) & 255) as usize] ^
+                        self.perm_y[((j + dj as i64) & 255) as usize] ^
+                        self.perm_z[((k + dk as i64) & 255) as usize]
+                    ];
+                }
+            }
+        }
+
+        return Self::perlin_interp(&c, u, v, w);
+    }
+
+    pub fn fbm(&self, p: &Point3, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        // Fractal Brownian motion: a sum of noise octaves, each one lacunarity times finer
+        // and gain times weaker than the one before
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(&temp_p);
+            weight *= gain;
+            temp_p *= lacunarity;
+        }
+
+        return accum;
+    }
+
+    pub fn turbulence(&self, p: &Point3, octaves: u32) -> f64 {
+        // Sum of absolute noise octaves, which gives creases where the noise changes sign
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(&temp_p).abs();
+            weight *= 0.5;
+            temp_p *= 2.0;
+        }
+
+        return accum;
+    }
+
+    fn perlin_generate_perm(rng: &mut StdRng) -> Vec<usize> {
+        let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+        p.shuffle(rng);
+        return p;
+    }
+
+    fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        // Trilinear interpolation of the corner gradients, with Hermite smoothing of the
+        // weights to hide the lattice
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+        let mut accum = 0.0;
+
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, corner) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight_v = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * vec3::dot(*corner, weight_v);
+                }
+            }
+        }
+
+        return accum;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::common;
+
+    fn sample(perlin: &Perlin) -> Vec<f64> {
+        // Noise on a grid of points away from the lattice, where it would always be zero
+        let mut values = Vec::new();
+        for i in 0..8 {
+            for j in 0..8 {
+                let p = Point3::new(0.37 * i as f64 + 0.11, 0.53 * j as f64 - 2.9, 0.29 * (i + j) as f64 + 0.7);
+                values.push(perlin.noise(&p));
+                values.push(perlin.turbulence(&p, 7));
+            }
+        }
+        return values;
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_field() {
+        // Whatever state the render's own generator is in
+        common::seed_rng(1);
+        let a = sample(&Perlin::new(42));
+        common::seed_rng(2);
+        common::random_double();
+        assert_eq!(a, sample(&Perlin::new(42)));
+    }
+
+    #[test]
+    fn different_seeds_give_different_fields() {
+        let a = sample(&Perlin::new(42));
+        let b = sample(&Perlin::new(43));
+        let differing = a.iter().zip(&b).filter(|(x, y)| (*x - *y).abs() > 1e-6).count();
+        assert!(differing > a.len() / 2, "only {} of {} values differ", differing, a.len());
+    }
+}
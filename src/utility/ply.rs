@@ -0,0 +1,471 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::utility::colors::Color;
+use crate::utility::material::{Lambertian, Material};
+use crate::utility::triangle::{MeshData, TriangleMesh};
+use crate::utility::vec3::{Point3, Vec3};
+
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, message: String },
+}
+
+impl Display for PlyError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            PlyError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            PlyError::Parse { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlyError::Io { source, .. } => Some(source),
+            PlyError::Parse { .. } => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(ScalarType::Int8),
+            "uchar" | "uint8" => Some(ScalarType::UInt8),
+            "short" | "int16" => Some(ScalarType::Int16),
+            "ushort" | "uint16" => Some(ScalarType::UInt16),
+            "int" | "int32" => Some(ScalarType::Int32),
+            "uint" | "uint32" => Some(ScalarType::UInt32),
+            "float" | "float32" => Some(ScalarType::Float32),
+            "double" | "float64" => Some(ScalarType::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    fn color_scale(&self) -> f64 {
+        // Integer color channels cover the full range of their type, floats are already [0,1]
+        match self {
+            ScalarType::UInt8 => 1.0 / 255.0,
+            ScalarType::UInt16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+enum PropertyKind {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| names.contains(&p.name.as_str()))
+    }
+}
+
+
+// Reads the values of the body in file order, from either the whitespace separated text of an
+// ASCII file or the packed bytes of a binary one
+struct BodyReader<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    pos: usize,
+    line: usize,
+    tokens: Vec<&'a str>,
+    token_pos: usize,
+}
+
+impl<'a> BodyReader<'a> {
+    fn next_line(&mut self) -> Result<(), String> {
+        // Each ASCII element instance occupies one line
+        loop {
+            if self.pos >= self.bytes.len() {
+                return Err(format!("line {}: unexpected end of file", self.line + 1));
+            }
+            let rest = &self.bytes[self.pos..];
+            let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+            let text = std::str::from_utf8(&rest[..end])
+                .map_err(|_| format!("line {}: invalid text", self.line + 1))?;
+            self.pos += usize::min(end + 1, rest.len());
+            self.line += 1;
+            self.tokens = text.split_whitespace().collect();
+            self.token_pos = 0;
+            if !self.tokens.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn end_instance(&mut self) -> Result<(), String> {
+        if self.format == Format::Ascii && self.token_pos != self.tokens.len() {
+            return Err(format!("line {}: expected {} values, found {}", self.line, self.token_pos, self.tokens.len()));
+        }
+        Ok(())
+    }
+
+    fn begin_instance(&mut self) -> Result<(), String> {
+        if self.format == Format::Ascii {
+            self.next_line()?;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            let token = self.tokens.get(self.token_pos)
+                .ok_or_else(|| format!("line {}: too few values", self.line))?;
+            self.token_pos += 1;
+            return token.parse::<f64>().map_err(|_| format!("line {}: invalid number '{}'", self.line, token));
+        }
+
+        let size = ty.size();
+        if self.pos + size > self.bytes.len() {
+            return Err(format!("byte {}: unexpected end of file", self.pos));
+        }
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(&self.bytes[self.pos..self.pos + size]);
+        self.pos += size;
+        if self.format == Format::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+
+        let value = match ty {
+            ScalarType::Int8 => raw[0] as i8 as f64,
+            ScalarType::UInt8 => raw[0] as f64,
+            ScalarType::Int16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::UInt16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::Int32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::UInt32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::Float32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::Float64 => f64::from_le_bytes(raw),
+        };
+        return Ok(value);
+    }
+
+    fn read_index(&mut self, ty: ScalarType) -> Result<usize, String> {
+        let value = self.read(ty)?;
+        if value < 0.0 || value.fract() != 0.0 {
+            return Err(format!("invalid index {}", value));
+        }
+        return Ok(value as usize);
+    }
+}
+
+
+fn parse_header(text: &str) -> Result<(Format, Vec<Element>), String> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, "ply")) => {}
+        _ => return Err("line 1: missing 'ply' magic number".to_string()),
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    for (i, line) in lines {
+        let line_no = i + 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(format!("line {}: unknown format '{}'", line_no, name)),
+                });
+            }
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| format!("line {}: invalid element count '{}'", line_no, count))?;
+                elements.push(Element { name: name.to_string(), count: count, properties: Vec::new() });
+            }
+            ["property", "list", count_ty, item_ty, name] => {
+                let element = elements.last_mut().ok_or_else(|| format!("line {}: property before any element", line_no))?;
+                let count = ScalarType::parse(count_ty).ok_or_else(|| format!("line {}: unknown type '{}'", line_no, count_ty))?;
+                let item = ScalarType::parse(item_ty).ok_or_else(|| format!("line {}: unknown type '{}'", line_no, item_ty))?;
+                element.properties.push(Property { name: name.to_string(), kind: PropertyKind::List { count: count, item: item } });
+            }
+            ["property", ty, name] => {
+                let element = elements.last_mut().ok_or_else(|| format!("line {}: property before any element", line_no))?;
+                let ty = ScalarType::parse(ty).ok_or_else(|| format!("line {}: unknown type '{}'", line_no, ty))?;
+                element.properties.push(Property { name: name.to_string(), kind: PropertyKind::Scalar(ty) });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            ["end_header"] => {
+                let format = format.ok_or_else(|| "header has no format line".to_string())?;
+                return Ok((format, elements));
+            }
+            _ => return Err(format!("line {}: malformed header line '{}'", line_no, line)),
+        }
+    }
+    return Err("missing end_header".to_string());
+}
+
+
+pub fn load_ply(path: impl AsRef<Path>, material: Option<Arc<dyn Material>>) -> Result<TriangleMesh, PlyError> {
+    // Load a PLY mesh. Without an explicit material the mesh is Lambertian, using the vertex
+    // colors as albedo when the file provides them
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| PlyError::Io { path: path.to_path_buf(), source: e })?;
+    let parse_error = |message: String| PlyError::Parse { path: path.to_path_buf(), message: message };
+
+    // The header ends at the first line that is exactly end_header, so comments mentioning it
+    // don't cut the header short
+    let mut header_lines = bytes.split_inclusive(|&b| b == b'\n');
+    let mut body_start = 0;
+    loop {
+        let line = header_lines.next().ok_or_else(|| parse_error("missing end_header".to_string()))?;
+        body_start += line.len();
+        if line.trim_ascii_end() == b"end_header" {
+            break;
+        }
+    }
+    let header = std::str::from_utf8(&bytes[..body_start])
+        .map_err(|_| parse_error("header is not valid text".to_string()))?;
+    let (format, elements) = parse_header(header).map_err(&parse_error)?;
+
+    let mut reader = BodyReader {
+        format: format,
+        bytes: &bytes[body_start..],
+        pos: 0,
+        line: header.lines().count(),
+        tokens: Vec::new(),
+        token_pos: 0,
+    };
+
+    let mut data = MeshData {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        colors: Vec::new(),
+        indices: Vec::new(),
+        material: Arc::new(Lambertian::new(Color::from_float(0.8))),
+    };
+
+    for element in &elements {
+        let position = [element.find(&["x"]), element.find(&["y"]), element.find(&["z"])];
+        let normal = [element.find(&["nx"]), element.find(&["ny"]), element.find(&["nz"])];
+        let uv = [element.find(&["u", "s", "texture_u", "texture_s"]), element.find(&["v", "t", "texture_v", "texture_t"])];
+        let color = [element.find(&["red", "r"]), element.find(&["green", "g"]), element.find(&["blue", "b"])];
+        let face_indices = element.find(&["vertex_indices", "vertex_index"]);
+
+        let is_vertex = element.name == "vertex";
+        let is_face = element.name == "face";
+        if is_vertex && position.contains(&None) {
+            return Err(parse_error("vertex element lacks x, y or z".to_string()));
+        }
+        if is_face && face_indices.is_none() {
+            return Err(parse_error("face element lacks vertex_indices".to_string()));
+        }
+        let has_normals = is_vertex && !normal.contains(&None);
+        let has_uvs = is_vertex && !uv.contains(&None);
+        let has_colors = is_vertex && !color.contains(&None);
+
+        let mut values = vec![0.0; element.properties.len()];
+        let mut scales = vec![1.0; element.properties.len()];
+        for _ in 0..element.count {
+            reader.begin_instance().map_err(&parse_error)?;
+            let mut face = Vec::new();
+
+            for (k, property) in element.properties.iter().enumerate() {
+                match property.kind {
+                    PropertyKind::Scalar(ty) => {
+                        values[k] = reader.read(ty).map_err(&parse_error)?;
+                        scales[k] = ty.color_scale();
+                    }
+                    PropertyKind::List { count, item } => {
+                        let n = reader.read_index(count).map_err(&parse_error)?;
+                        for _ in 0..n {
+                            if Some(k) == face_indices && is_face {
+                                face.push(reader.read_index(item).map_err(&parse_error)?);
+                            } else {
+                                reader.read(item).map_err(&parse_error)?;
+                            }
+                        }
+                    }
+                }
+            }
+            reader.end_instance().map_err(&parse_error)?;
+
+            let get = |k: Option<usize>| values[k.unwrap()];
+            if is_vertex {
+                data.positions.push(Point3::new(get(position[0]), get(position[1]), get(position[2])));
+                if has_normals {
+                    data.normals.push(Vec3::new(get(normal[0]), get(normal[1]), get(normal[2])));
+                }
+                if has_uvs {
+                    data.uvs.push((get(uv[0]), get(uv[1])));
+                }
+                if has_colors {
+                    let channel = |k: Option<usize>| values[k.unwrap()] * scales[k.unwrap()];
+                    data.colors.push(Color::new(channel(color[0]), channel(color[1]), channel(color[2])));
+                }
+            } else if is_face {
+                if face.len() < 3 {
+                    return Err(parse_error(format!("face {} has fewer than 3 vertices", data.indices.len())));
+                }
+                for k in 1..face.len() - 1 {
+                    data.indices.push([face[0], face[k], face[k + 1]]);
+                }
+            }
+        }
+    }
+
+    data.material = match material {
+        Some(material) => material,
+        None if !data.colors.is_empty() => Arc::new(Lambertian::vertex_colored(Color::from_float(0.8))),
+        None => data.material,
+    };
+    return TriangleMesh::from_data(data).map_err(|e| parse_error(e.to_string()));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [[f32; 3]; 4] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 2.0, 0.0], [0.0, 2.0, -0.5]];
+    const COLORS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [51, 102, 153]];
+
+    fn header(format: &str) -> String {
+        return format!("ply\nformat {} 1.0\ncomment test quad\n\
+element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+property uchar red\nproperty uchar green\nproperty uchar blue\n\
+element face 1\nproperty list uchar int vertex_indices\nend_header\n", format);
+    }
+
+    fn write_ply(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ply_test_{}_{}.ply", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        return path;
+    }
+
+    fn binary(format: &str, big_endian: bool) -> Vec<u8> {
+        let mut bytes = header(format).into_bytes();
+        for (position, color) in POSITIONS.iter().zip(COLORS.iter()) {
+            for x in position {
+                bytes.extend(if big_endian { x.to_be_bytes() } else { x.to_le_bytes() });
+            }
+            bytes.extend(color);
+        }
+        bytes.push(4);
+        for i in 0..4i32 {
+            bytes.extend(if big_endian { i.to_be_bytes() } else { i.to_le_bytes() });
+        }
+        return bytes;
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        return (a - b).length() < 1e-9;
+    }
+
+    fn check_quad(mesh: &TriangleMesh) {
+        let data = mesh.data();
+        assert_eq!(data.positions.len(), 4);
+        for (position, expected) in data.positions.iter().zip(POSITIONS) {
+            assert!(close(*position, Point3::new(expected[0] as f64, expected[1] as f64, expected[2] as f64)));
+        }
+        // 8 bit color channels are scaled to [0,1]
+        assert!(close(data.colors[0], Color::new(1.0, 0.0, 0.0)));
+        assert!(close(data.colors[3], Color::new(0.2, 0.4, 0.6)));
+        assert!(data.normals.is_empty() && data.uvs.is_empty());
+        // The quad is split as a fan around its first vertex
+        assert_eq!(data.indices, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn loads_ascii() {
+        let mut text = header("ascii");
+        text.push_str("0 0 0 255 0 0\n1 0 0 0 255 0\n\n1 2 0 0 0 255\n0 2 -0.5 51 102 153\n4 0 1 2 3\n");
+        check_quad(&load_ply(write_ply("ascii", text.as_bytes()), None).unwrap());
+    }
+
+    #[test]
+    fn loads_binary_little_endian() {
+        check_quad(&load_ply(write_ply("le", &binary("binary_little_endian", false)), None).unwrap());
+    }
+
+    #[test]
+    fn loads_binary_big_endian() {
+        check_quad(&load_ply(write_ply("be", &binary("binary_big_endian", true)), None).unwrap());
+    }
+
+    #[test]
+    fn header_ends_only_at_an_end_header_line() {
+        let text = header("ascii").replace("comment test quad\n", "comment exported without end_header\ncomment end_header\n")
+            + "0 0 0 255 0 0\n1 0 0 0 255 0\n1 2 0 0 0 255\n0 2 -0.5 51 102 153\n4 0 1 2 3\n";
+        check_quad(&load_ply(write_ply("comment", text.as_bytes()), None).unwrap());
+
+        let text = header("ascii").replace("end_header\n", "end_header_\n");
+        match load_ply(write_ply("no_end", text.as_bytes()), None) {
+            Err(PlyError::Parse { message, .. }) => assert!(message.contains("missing end_header"), "{}", message),
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn reports_truncated_and_malformed_files() {
+        let mut bytes = binary("binary_little_endian", false);
+        bytes.truncate(bytes.len() - 2);
+        assert!(matches!(load_ply(write_ply("truncated", &bytes), None), Err(PlyError::Parse { .. })));
+
+        let mut text = header("ascii");
+        text.push_str("0 0 0 255 0 0\n1 0 0 0 255 0\n1 2 0 0 0 255\n0 2 -0.5 51 102 153\n3 0 1 7\n");
+        match load_ply(write_ply("bad_index", text.as_bytes()), None) {
+            Err(PlyError::Parse { message, .. }) => assert!(message.contains("vertex 7 of 4"), "{}", message),
+            _ => panic!("expected a parse error"),
+        }
+
+        let text = header("ascii") + "0 0 0 255 0 0\n1 x 0 0 255 0\n";
+        match load_ply(write_ply("bad_number", text.as_bytes()), None) {
+            Err(PlyError::Parse { message, .. }) => assert!(message.contains("line 15"), "{}", message),
+            _ => panic!("expected a parse error"),
+        }
+    }
+}
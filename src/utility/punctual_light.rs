@@ -0,0 +1,217 @@
+use crate::utility::colors::Color;
+use crate::utility::common;
+use crate::utility::vec3::{self, Point3, Vec3};
+
+
+// Lights that are not geometry: they sit at a single point or come from a single direction,
+// so rays never hit them and they are only reached through shadow rays. Point and spot
+// intensities are radiant intensities, falling off with the inverse square of the distance
+// and, when a range is set, smoothly fading to zero at the range. The directional irradiance
+// is measured on a surface facing the light
+pub enum PunctualLight {
+    Point { position: Point3, intensity: Color, range: Option<f64> },
+    Spot {
+        position: Point3,
+        direction: Vec3, // Unit vector the spot points along
+        intensity: Color,
+        cos_inner: f64, // Cosine of the cone angle within which the spot is at full intensity
+        cos_outer: f64, // Cosine of the cone angle where the spot has faded out
+        range: Option<f64>,
+    },
+    Directional { direction: Vec3, irradiance: Color }, // Unit vector the light travels along
+}
+
+impl PunctualLight {
+    pub fn point(position: Point3, intensity: Color) -> Self {
+        PunctualLight::Point { position: position, intensity: intensity, range: None }
+    }
+
+    pub fn spot(position: Point3, direction: Vec3, intensity: Color, inner_angle: f64, outer_angle: f64) -> Self {
+        // The cone angles are measured in degrees from the spot direction to the edge. The
+        // outer angle is clamped to [0,90] and the inner one to [0,outer]
+        let outer_angle = outer_angle.clamp(0.0, 90.0);
+        let inner_angle = inner_angle.clamp(0.0, outer_angle);
+        PunctualLight::Spot {
+            position: position,
+            direction: vec3::unit_vector(direction),
+            intensity: intensity,
+            cos_inner: common::degrees_to_radians(inner_angle).cos(),
+            cos_outer: common::degrees_to_radians(outer_angle).cos(),
+            range: None,
+        }
+    }
+
+    pub fn directional(direction: Vec3, irradiance: Color) -> Self {
+        PunctualLight::Directional { direction: vec3::unit_vector(direction), irradiance: irradiance }
+    }
+
+    pub fn with_range(mut self, new_range: f64) -> Self {
+        // Distance at which point and spot lights fade out completely. Directional lights have
+        // no range and are returned unchanged
+        if let PunctualLight::Point { ref mut range, .. } | PunctualLight::Spot { ref mut range, .. } = self {
+            *range = Some(new_range);
+        }
+        return self;
+    }
+
+    pub fn sample(&self, p: Point3) -> Option<(Vec3, f64, Color)> {
+        // Light arriving at p: the unit direction towards the light, the distance the shadow
+        // ray has to cover, and the incident radiance integrated over the delta direction
+        match self {
+            PunctualLight::Point { position, intensity, range } => {
+                let (direction, distance) = towards(p, *position)?;
+                Some((direction, distance, falloff(distance, *range) * *intensity))
+            }
+            PunctualLight::Spot { position, direction: spot_direction, intensity, cos_inner, cos_outer, range } => {
+                let (direction, distance) = towards(p, *position)?;
+                let cone = smoothstep(*cos_outer, *cos_inner, vec3::dot(-direction, *spot_direction));
+                if cone <= 0.0 {
+                    return None;
+                }
+                Some((direction, distance, cone * falloff(distance, *range) * *intensity))
+            }
+            PunctualLight::Directional { direction, irradiance } => Some((-*direction, common::INFINITY, *irradiance)),
+        }
+    }
+}
+
+
+fn towards(p: Point3, position: Point3) -> Option<(Vec3, f64)> {
+    let offset = position - p;
+    let distance = offset.length();
+    if distance <= 0.0 {
+        return None;
+    }
+    return Some((offset / distance, distance));
+}
+
+fn falloff(distance: f64, range: Option<f64>) -> f64 {
+    // Inverse square law, windowed so it reaches zero at the range, as in glTF's
+    // KHR_lights_punctual
+    let window = match range {
+        Some(range) => (1.0 - (distance / range).powi(4)).clamp(0.0, 1.0).powi(2),
+        None => 1.0,
+    };
+    return window / (distance * distance);
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    if edge0 >= edge1 {
+        // A hard edged cone
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    return t * t * (3.0 - 2.0 * t);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::background::Background;
+    use crate::utility::camera::Camera;
+    use crate::utility::hittable_list::HittableList;
+    use crate::utility::light_list::LightList;
+    use crate::utility::material::Lambertian;
+    use crate::utility::quad::Quad;
+    use std::sync::Arc;
+
+    fn radiance(light: &PunctualLight, p: Point3) -> f64 {
+        return light.sample(p).map_or(0.0, |(_, _, radiance)| radiance.x);
+    }
+
+    #[test]
+    fn spot_fades_between_the_inner_and_outer_cone() {
+        // A spot at the origin pointing down -z, seen from points at distance 1
+        let spot = PunctualLight::spot(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0), Color::from_float(1.0), 20.0, 40.0);
+        let at = |degrees: f64| {
+            let angle = common::degrees_to_radians(degrees);
+            radiance(&spot, Point3::new(angle.sin(), 0.0, -angle.cos()))
+        };
+
+        assert!((at(0.0) - 1.0).abs() < 1e-12);
+        assert!((at(19.0) - 1.0).abs() < 1e-12);
+        assert_eq!(at(41.0), 0.0);
+        assert_eq!(at(120.0), 0.0);
+        let fading: Vec<f64> = (21..40).map(|degrees| at(degrees as f64)).collect();
+        assert!(fading.windows(2).all(|w| w[1] < w[0]), "{:?}", fading);
+        assert!(fading.iter().all(|&x| 0.0 < x && x < 1.0));
+    }
+
+    #[test]
+    fn spot_angles_out_of_order_are_clamped() {
+        // The inner cone can't be wider than the outer one, which can't pass 90 degrees
+        let spot = PunctualLight::spot(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), Color::from_float(1.0), 60.0, 30.0);
+        let PunctualLight::Spot { cos_inner, cos_outer, .. } = spot else { panic!("not a spot light") };
+        assert!((cos_inner - cos_outer).abs() < 1e-12);
+
+        let spot = PunctualLight::spot(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), Color::from_float(1.0), -10.0, 135.0);
+        let PunctualLight::Spot { cos_inner, cos_outer, .. } = spot else { panic!("not a spot light") };
+        assert!((cos_inner - 1.0).abs() < 1e-12 && cos_outer.abs() < 1e-12);
+        assert_eq!(radiance(&spot, Point3::new(0.0, 1.0, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn range_windows_the_inverse_square_falloff() {
+        let light = PunctualLight::point(Point3::new(0.0, 0.0, 0.0), Color::from_float(8.0));
+        let ranged = PunctualLight::point(Point3::new(0.0, 0.0, 0.0), Color::from_float(8.0)).with_range(4.0);
+
+        // Without a range the light falls off with the square of the distance
+        assert!((radiance(&light, Point3::new(2.0, 0.0, 0.0)) - 2.0).abs() < 1e-12);
+        assert!((radiance(&light, Point3::new(0.0, 0.0, 8.0)) - 0.125).abs() < 1e-12);
+
+        // The window is (1 - (d/range)^4)^2, reaching zero at the range
+        let window = (1.0 - f64::powi(0.5, 4)).powi(2);
+        assert!((radiance(&ranged, Point3::new(2.0, 0.0, 0.0)) - 2.0 * window).abs() < 1e-12);
+        assert_eq!(radiance(&ranged, Point3::new(0.0, 4.0, 0.0)), 0.0);
+        assert_eq!(radiance(&ranged, Point3::new(0.0, 0.0, 8.0)), 0.0);
+
+        // Directional lights have no range
+        let sun = PunctualLight::directional(Vec3::new(0.0, -1.0, 0.0), Color::from_float(3.0)).with_range(1.0);
+        assert_eq!(sun.sample(Point3::new(0.0, -100.0, 0.0)).unwrap().2.x, 3.0);
+    }
+
+    fn render_floor_under_point_light(occluded: bool) -> Vec<f64> {
+        // A grey floor lit by a point light 4 above it, seen from straight above. An optional
+        // black square halfway up casts a shadow covering the middle of the view
+        let mut world = HittableList::new();
+        world.add(Box::new(Quad::new(Point3::new(-5.0, 0.0, -5.0), Vec3::new(10.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 10.0),
+            Arc::new(Lambertian::new(Color::from_float(0.5))))));
+        if occluded {
+            world.add(Box::new(Quad::new(Point3::new(-0.5, 2.0, -0.5), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0),
+                Arc::new(Lambertian::new(Color::from_float(0.0))))));
+        }
+        let light = PunctualLight::point(Point3::new(0.0, 4.0, 0.0), Color::from_float(10.0));
+        let lights = LightList::new(Vec::new()).with_punctual_lights(vec![light]);
+
+        let mut camera = Camera::new();
+        camera.aspect_ratio = 1.0;
+        camera.image_width = 5;
+        camera.samples_per_pixel = 4;
+        camera.max_depth = 3;
+        camera.vfov = 90.0;
+        camera.look_from = Point3::new(0.0, 1.5, 0.0);
+        camera.look_at = Point3::new(0.0, 0.0, 0.0);
+        camera.vup = Vec3::new(0.0, 0.0, -1.0);
+        camera.focus_dist = 1.0;
+        camera.seed = Some(1);
+        camera.background = Background::Solid(Color::from_float(0.0));
+        camera.initialize();
+        return camera.render(&world, &lights).pixels.iter().map(|c| c.x).collect();
+    }
+
+    #[test]
+    fn occluded_point_light_casts_a_shadow() {
+        let lit = render_floor_under_point_light(false);
+        let shadowed = render_floor_under_point_light(true);
+        let (center, corner) = (2 * 5 + 2, 0);
+
+        // Straight below the light the floor reflects albedo / pi of the irradiance I / d^2
+        let expected = 0.5 / common::PI * 10.0 / 16.0;
+        assert!((lit[center] - expected).abs() < 0.02 * expected, "{} vs {}", lit[center], expected);
+
+        assert_eq!(shadowed[center], 0.0);
+        assert!(shadowed[corner] > 0.0);
+        assert!((shadowed[corner] - lit[corner]).abs() < 1e-12);
+    }
+}
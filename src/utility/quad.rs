@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use crate::utility::aabb::Aabb;
+use crate::utility::common;
+use crate::utility::hittable::{HitRecord, Hittable};
+use crate::utility::hittable_list::HittableList;
+use crate::utility::interval::Interval;
+use crate::utility::material::Material;
+use crate::utility::ray::Ray;
+use crate::utility::vec3::{self, Point3, Vec3};
+
+
+// A parallelogram with corner q and edges u and v. Its front face is the side the normal
+// cross(u, v) points to
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3, // Constant for the planar coordinates of hit points
+    material: Arc<dyn Material>,
+    bbox: Aabb,
+    normal: Vec3,
+    d: f64, // Plane equation offset, dot(normal, p) = d for points on the plane
+    area: f64,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Self {
+        let n = vec3::cross(u, v);
+        let normal = vec3::unit_vector(n);
+
+        // Compute the bounding box of all four vertices
+        let bbox_diagonal1 = Aabb::from_points(q, q + u + v);
+        let bbox_diagonal2 = Aabb::from_points(q + u, q + v);
+
+        Quad {
+            q: q,
+            u: u,
+            v: v,
+            w: n / vec3::dot(n, n),
+            material: material,
+            bbox: Aabb::surrounding(&bbox_diagonal1, &bbox_diagonal2).pad_to_minimums(),
+            normal: normal,
+            d: vec3::dot(normal, q),
+            area: n.length(),
+        }
+    }
+
+    fn is_interior(a: f64, b: f64) -> bool {
+        // Given the hit point in plane coordinates, return false if it is outside the
+        // primitive
+        let unit_interval = Interval::new(0.0, 1.0);
+        return unit_interval.contains(a) && unit_interval.contains(b);
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let denom = vec3::dot(self.normal, r.direction());
+
+        // No hit if the ray is parallel to the plane
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        // Return false if the hit point parameter t is outside the ray interval
+        let t = (self.d - vec3::dot(self.normal, r.origin())) / denom;
+        if !ray_t.contains(t) {
+            return None;
+        }
+
+        // Determine if the hit point lies within the planar shape using its plane coordinates
+        let intersection = r.at(t);
+        let planar_hitpt_vector = intersection - self.q;
+        let alpha = vec3::dot(self.w, vec3::cross(planar_hitpt_vector, self.v));
+        let beta = vec3::dot(self.w, vec3::cross(self.u, planar_hitpt_vector));
+        if !Self::is_interior(alpha, beta) {
+            return None;
+        }
+
+        let mut rec = HitRecord {
+            t: t,
+            p: intersection,
+            mat: self.material.clone(),
+            normal: Default::default(),
+            u: alpha,
+            v: beta,
+            barycentric: (0.0, 0.0),
+            color: None,
+            object_id: 0,
+            front_face: Default::default(),
+        };
+        rec.set_face_normal(r, self.normal);
+        return Some(rec);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let Some(rec) = self.hit(&Ray::new(origin, direction), Interval::new(0.001, common::INFINITY)) else {
+            return 0.0;
+        };
+
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = f64::abs(vec3::dot(direction, rec.normal) / direction.length());
+
+        return distance_squared / (cosine * self.area);
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        let p = self.q + (common::random_double() * self.u) + (common::random_double() * self.v);
+        return p - origin;
+    }
+}
+
+
+pub fn make_box(a: Point3, b: Point3, material: Arc<dyn Material>) -> HittableList {
+    // Returns the 3D box (six sides) that contains the two opposite vertices a & b
+    return make_rotated_box(a, b, 0.0, material);
+}
+
+pub fn make_rotated_box(a: Point3, b: Point3, rotate_y: f64, material: Arc<dyn Material>) -> HittableList {
+    // Like make_box, with the box turned by rotate_y degrees around the vertical axis through
+    // its center
+    let mut sides = HittableList::new();
+
+    // Construct the two opposite vertices with the minimum and maximum coordinates
+    let min = Point3::new(f64::min(a.x, b.x), f64::min(a.y, b.y), f64::min(a.z, b.z));
+    let max = Point3::new(f64::max(a.x, b.x), f64::max(a.y, b.y), f64::max(a.z, b.z));
+
+    let (sin_theta, cos_theta) = common::degrees_to_radians(rotate_y).sin_cos();
+    let rotate = |v: Vec3| Vec3::new(cos_theta * v.x + sin_theta * v.z, v.y, -sin_theta * v.x + cos_theta * v.z);
+    let center = 0.5 * (min + max);
+    let corner = |p: Point3| center + rotate(p - center);
+
+    let dx = rotate(Vec3::new(max.x - min.x, 0.0, 0.0));
+    let dy = rotate(Vec3::new(0.0, max.y - min.y, 0.0));
+    let dz = rotate(Vec3::new(0.0, 0.0, max.z - min.z));
+
+    sides.add(Box::new(Quad::new(corner(Point3::new(min.x, min.y, max.z)), dx, dy, material.clone()))); // front
+    sides.add(Box::new(Quad::new(corner(Point3::new(max.x, min.y, max.z)), -dz, dy, material.clone()))); // right
+    sides.add(Box::new(Quad::new(corner(Point3::new(max.x, min.y, min.z)), -dx, dy, material.clone()))); // back
+    sides.add(Box::new(Quad::new(corner(Point3::new(min.x, min.y, min.z)), dz, dy, material.clone()))); // left
+    sides.add(Box::new(Quad::new(corner(Point3::new(min.x, max.y, max.z)), dx, -dz, material.clone()))); // top
+    sides.add(Box::new(Quad::new(corner(Point3::new(min.x, min.y, min.z)), dx, dz, material))); // bottom
+
+    return sides;
+}
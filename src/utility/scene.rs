@@ -0,0 +1,907 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::utility::background::{Background, EnvironmentMap};
+use crate::utility::camera::Camera;
+use crate::utility::colors;
+use crate::utility::common;
+use crate::utility::gltf_import::{self, GltfCamera};
+use crate::utility::hittable::Hittable;
+use crate::utility::hittable_list::HittableList;
+use crate::utility::light_list::{LightList, LightSampling};
+use crate::utility::linear_bvh::LinearBvh;
+use crate::utility::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::utility::obj;
+use crate::utility::ply;
+use crate::utility::punctual_light::PunctualLight;
+use crate::utility::quad::{self, Quad};
+use crate::utility::sky::PhysicalSky;
+use crate::utility::sphere::Sphere;
+use crate::utility::texture::{
+    CheckerTexture, CloudTexture, ImageTexture, MarbleTexture, NoiseTexture, SolidColor, Texture, TextureError,
+    WoodTexture, WrapMode,
+};
+use crate::utility::tonemap::{ToneMapOperator, ToneMapping};
+use crate::utility::triangle::{Triangle, TriangleMesh};
+use crate::utility::vec3::{cross, Point3, Vec3};
+
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io { path: PathBuf, source: io::Error },
+    Syntax { path: PathBuf, source: serde_json::Error },
+    Invalid { path: PathBuf, message: String },
+    Mesh { path: PathBuf, source: Box<dyn Error + Send + Sync> },
+    Texture { path: PathBuf, source: TextureError },
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Syntax { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Invalid { path, message } => write!(f, "{}: {}", path.display(), message),
+            SceneError::Mesh { path, source } => write!(f, "{}: while loading mesh: {}", path.display(), source),
+            SceneError::Texture { path, source } => write!(f, "{}: while loading texture: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Syntax { source, .. } => Some(source),
+            SceneError::Invalid { .. } => None,
+            SceneError::Mesh { source, .. } => Some(source.as_ref()),
+            SceneError::Texture { source, .. } => Some(source),
+        }
+    }
+}
+
+
+// File representation of a scene. Vectors are written as [x, y, z] arrays
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    camera: Option<CameraDesc>, // Without one, the first glTF camera sets the view
+    #[serde(default)]
+    textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    tone_mapping: ToneMappingDesc,
+    #[serde(default)]
+    light_sampling: LightSamplingDesc,
+    #[serde(default)]
+    lights: Vec<LightDesc>, // Punctual lights, which aren't part of the geometry
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum LightSamplingDesc {
+    Uniform,
+    #[default]
+    Power,
+    Bvh,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct ToneMappingDesc {
+    operator: OperatorDesc,
+    exposure: f64,
+    white_point: f64,
+}
+
+impl Default for ToneMappingDesc {
+    fn default() -> Self {
+        ToneMappingDesc { operator: OperatorDesc::Clamp, exposure: 0.0, white_point: 4.0 }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OperatorDesc {
+    Clamp,
+    Reinhard,
+    ReinhardExtended,
+    Aces,
+    Agx,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct CameraDesc {
+    aspect_ratio: f64,
+    image_width: i32,
+    samples_per_pixel: i32,
+    max_depth: i32,
+    min_depth: i32,
+    vfov: f64,
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    vup: [f64; 3],
+    defocus_angle: f64,
+    focus_dist: f64,
+    background: Option<BackgroundDesc>,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        CameraDesc {
+            aspect_ratio: 16.0 / 9.0,
+            image_width: 400,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            min_depth: 5,
+            vfov: 90.0,
+            look_from: [0.0, 0.0, 0.0],
+            look_at: [0.0, 0.0, -1.0],
+            vup: [0.0, 1.0, 0.0],
+            defocus_angle: 0.0,
+            focus_dist: 10.0,
+            background: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    Gradient {
+        bottom: [f64; 3],
+        top: [f64; 3],
+    },
+    Environment {
+        path: PathBuf,
+        #[serde(default)]
+        rotation: f64, // Degrees around the vertical axis
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+    Sky {
+        sun_elevation: f64, // Degrees above the horizon
+        #[serde(default)]
+        sun_azimuth: f64, // Degrees from -z towards +x
+        #[serde(default = "default_turbidity")]
+        turbidity: f64,
+        #[serde(default = "default_ground_albedo")]
+        ground_albedo: [f64; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_ground_albedo() -> [f64; 3] {
+    [0.3, 0.3, 0.3]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        scale: f64,
+        even: [f64; 3],
+        odd: [f64; 3],
+    },
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        wrap: WrapDesc,
+    },
+    // The noise textures default to seed 0, so every render sees the same pattern
+    Noise {
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+    },
+    Marble {
+        scale: f64,
+        base: [f64; 3],
+        vein: [f64; 3],
+        #[serde(default)]
+        seed: u64,
+    },
+    Wood {
+        scale: f64,
+        light: [f64; 3],
+        dark: [f64; 3],
+        #[serde(default)]
+        seed: u64,
+    },
+    Clouds {
+        scale: f64,
+        sky: [f64; 3],
+        cloud: [f64; 3],
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum WrapDesc {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+// A material albedo is either a constant color or the name of a texture
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AlbedoDesc {
+    Color([f64; 3]),
+    Texture(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: AlbedoDesc,
+        #[serde(default)]
+        vertex_colors: bool,
+    },
+    Metal {
+        albedo: AlbedoDesc,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        refraction_index: f64,
+    },
+    DiffuseLight {
+        emit: AlbedoDesc,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: String,
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        material: String,
+    },
+    Quad {
+        q: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        material: String,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        #[serde(default)]
+        rotate_y: f64, // Degrees around the vertical axis through the box center
+        material: String,
+    },
+    Mesh {
+        path: PathBuf,
+        material: Option<String>,
+    },
+}
+
+// Emission is color times intensity. Point and spot lights give a radiant intensity, directional
+// lights the irradiance on a surface facing them
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDesc {
+    Point {
+        position: [f64; 3],
+        #[serde(default = "default_light_color")]
+        color: [f64; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+        range: Option<f64>,
+    },
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        #[serde(default = "default_light_color")]
+        color: [f64; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+        #[serde(default)]
+        inner_angle: f64, // Degrees from the spot direction, full intensity inside
+        #[serde(default = "default_outer_angle")]
+        outer_angle: f64, // Degrees from the spot direction, dark outside
+        range: Option<f64>,
+    },
+    Directional {
+        direction: [f64; 3], // Direction the light travels in
+        #[serde(default = "default_light_color")]
+        color: [f64; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+}
+
+fn default_light_color() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_outer_angle() -> f64 {
+    45.0
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+
+fn light_power(material: &dyn Material, area: f64, center: Point3) -> f64 {
+    // Power estimate for picking lights, from the emission at the center of the object
+    return area * colors::luminance(material.emitted(0.5, 0.5, &center));
+}
+
+fn mesh_lights(mesh: &TriangleMesh, lights: &mut Vec<(Box<dyn Hittable>, f64)>) {
+    // Every emissive triangle of a mesh is sampled as a light of its own, whether the emission
+    // comes from a material override or from the file's own materials
+    let data = mesh.data();
+    let power: Vec<f64> = data
+        .indices
+        .iter()
+        .enumerate()
+        .map(|(index, &[i0, i1, i2])| {
+            let center = (data.positions[i0] + data.positions[i1] + data.positions[i2]) / 3.0;
+            light_power(data.material.as_ref(), mesh.triangle_area(index), center)
+        })
+        .collect();
+    if power.iter().all(|&p| p <= 0.0) {
+        return;
+    }
+    for (triangle, power) in mesh.triangles().into_iter().zip(power) {
+        if power > 0.0 {
+            lights.push((triangle, power));
+        }
+    }
+}
+
+
+pub struct Scene {
+    pub camera: Camera,
+    pub world: HittableList,
+    pub lights: LightList, // Copies of the emissive spheres, triangles, quads, boxes and mesh triangles, and the punctual lights
+    pub tone_mapping: ToneMapping,
+    pub warnings: Vec<String>, // Problems in referenced files that were worked around
+}
+
+
+struct SceneBuilder<'a> {
+    path: &'a Path,
+    textures: BTreeMap<String, Arc<dyn Texture>>,
+    materials: BTreeMap<String, Arc<dyn Material>>,
+    light_materials: HashSet<String>, // Names of the emissive materials
+}
+
+impl SceneBuilder<'_> {
+    fn invalid(&self, message: String) -> SceneError {
+        SceneError::Invalid { path: self.path.to_path_buf(), message: message }
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        // Image and mesh paths are relative to the scene file
+        return self.path.parent().unwrap_or(Path::new("")).join(path);
+    }
+
+    fn camera(&self, desc: &CameraDesc) -> Result<Camera, SceneError> {
+        if desc.image_width < 1 {
+            return Err(self.invalid(format!("camera: image_width must be at least 1, got {}", desc.image_width)));
+        }
+        if desc.aspect_ratio <= 0.0 {
+            return Err(self.invalid(format!("camera: aspect_ratio must be positive, got {}", desc.aspect_ratio)));
+        }
+        if desc.samples_per_pixel < 1 {
+            return Err(self.invalid(format!("camera: samples_per_pixel must be at least 1, got {}", desc.samples_per_pixel)));
+        }
+        if desc.max_depth < 0 {
+            return Err(self.invalid(format!("camera: max_depth must not be negative, got {}", desc.max_depth)));
+        }
+        if desc.min_depth < 0 {
+            return Err(self.invalid(format!("camera: min_depth must not be negative, got {}", desc.min_depth)));
+        }
+        if desc.vfov <= 0.0 || desc.vfov >= 180.0 {
+            return Err(self.invalid(format!("camera: vfov must be between 0 and 180 degrees, got {}", desc.vfov)));
+        }
+        if desc.defocus_angle < 0.0 {
+            return Err(self.invalid(format!("camera: defocus_angle must not be negative, got {}", desc.defocus_angle)));
+        }
+        if desc.focus_dist <= 0.0 {
+            return Err(self.invalid(format!("camera: focus_dist must be positive, got {}", desc.focus_dist)));
+        }
+        if desc.look_from == desc.look_at {
+            return Err(self.invalid("camera: look_from and look_at must differ".to_string()));
+        }
+
+        let mut camera = Camera::new();
+        camera.aspect_ratio = desc.aspect_ratio;
+        camera.image_width = desc.image_width;
+        camera.samples_per_pixel = desc.samples_per_pixel;
+        camera.max_depth = desc.max_depth;
+        camera.min_depth = desc.min_depth;
+        camera.vfov = desc.vfov;
+        camera.look_from = vec3(desc.look_from);
+        camera.look_at = vec3(desc.look_at);
+        camera.vup = vec3(desc.vup);
+        camera.defocus_angle = desc.defocus_angle;
+        camera.focus_dist = desc.focus_dist;
+        if let Some(background) = &desc.background {
+            camera.background = self.background(background)?;
+        }
+        return Ok(camera);
+    }
+
+    fn background(&self, desc: &BackgroundDesc) -> Result<Background, SceneError> {
+        let background = match desc {
+            BackgroundDesc::Solid { color } => Background::Solid(vec3(*color)),
+            BackgroundDesc::Gradient { bottom, top } => Background::Gradient { bottom: vec3(*bottom), top: vec3(*top) },
+            BackgroundDesc::Environment { path, rotation, intensity } => {
+                if *intensity < 0.0 {
+                    return Err(self.invalid(format!("camera: background intensity must not be negative, got {}", intensity)));
+                }
+                let map = EnvironmentMap::load(self.resolve(path))
+                    .map_err(|e| SceneError::Texture { path: self.path.to_path_buf(), source: e })?;
+                Background::Environment(map.with_rotation(*rotation).with_intensity(*intensity))
+            }
+            BackgroundDesc::Sky { sun_elevation, sun_azimuth, turbidity, ground_albedo, intensity } => {
+                if !(0.0..=90.0).contains(sun_elevation) {
+                    return Err(self.invalid(format!("camera: sun_elevation must be between 0 and 90 degrees, got {}", sun_elevation)));
+                }
+                if !(1.7..=10.0).contains(turbidity) {
+                    return Err(self.invalid(format!("camera: turbidity must be between 1.7 and 10, got {}", turbidity)));
+                }
+                if *intensity < 0.0 {
+                    return Err(self.invalid(format!("camera: background intensity must not be negative, got {}", intensity)));
+                }
+                let sky = PhysicalSky::new(*sun_elevation, *sun_azimuth, *turbidity, vec3(*ground_albedo));
+                Background::Sky(sky.with_intensity(*intensity))
+            }
+        };
+        return Ok(background);
+    }
+
+    fn tone_mapping(&self, desc: &ToneMappingDesc) -> Result<ToneMapping, SceneError> {
+        if desc.white_point <= 0.0 {
+            return Err(self.invalid(format!("tone_mapping: white_point must be positive, got {}", desc.white_point)));
+        }
+        let operator = match desc.operator {
+            OperatorDesc::Clamp => ToneMapOperator::Clamp,
+            OperatorDesc::Reinhard => ToneMapOperator::Reinhard,
+            OperatorDesc::ReinhardExtended => ToneMapOperator::ReinhardExtended { white_point: desc.white_point },
+            OperatorDesc::Aces => ToneMapOperator::Aces,
+            OperatorDesc::Agx => ToneMapOperator::AgX,
+        };
+        return Ok(ToneMapping::new(desc.exposure, operator));
+    }
+
+    fn punctual_light(&self, index: usize, desc: &LightDesc) -> Result<PunctualLight, SceneError> {
+        let (color, intensity, range) = match desc {
+            LightDesc::Point { color, intensity, range, .. } | LightDesc::Spot { color, intensity, range, .. } => {
+                (color, intensity, *range)
+            }
+            LightDesc::Directional { color, intensity, .. } => (color, intensity, None),
+        };
+        if *intensity < 0.0 || color.iter().any(|&c| c < 0.0) {
+            return Err(self.invalid(format!("light {}: color and intensity must not be negative", index)));
+        }
+        if range.is_some_and(|range| range <= 0.0) {
+            return Err(self.invalid(format!("light {}: range must be positive", index)));
+        }
+        let emission = *intensity * vec3(*color);
+
+        let light = match desc {
+            LightDesc::Point { position, .. } => PunctualLight::point(vec3(*position), emission),
+            LightDesc::Spot { position, direction, inner_angle, outer_angle, .. } => {
+                if vec3(*direction).near_zero() {
+                    return Err(self.invalid(format!("light {} (spot): direction must not be zero", index)));
+                }
+                if !(0.0 <= *inner_angle && inner_angle <= outer_angle && *outer_angle <= 90.0) {
+                    return Err(self.invalid(format!(
+                        "light {} (spot): angles need 0 <= inner_angle <= outer_angle <= 90, got {} and {}",
+                        index, inner_angle, outer_angle)));
+                }
+                PunctualLight::spot(vec3(*position), vec3(*direction), emission, *inner_angle, *outer_angle)
+            }
+            LightDesc::Directional { direction, .. } => {
+                if vec3(*direction).near_zero() {
+                    return Err(self.invalid(format!("light {} (directional): direction must not be zero", index)));
+                }
+                PunctualLight::directional(vec3(*direction), emission)
+            }
+        };
+        return Ok(match range {
+            Some(range) => light.with_range(range),
+            None => light,
+        });
+    }
+
+    fn texture(&self, name: &str, desc: &TextureDesc) -> Result<Arc<dyn Texture>, SceneError> {
+        let scale = match desc {
+            TextureDesc::Solid { .. } | TextureDesc::Image { .. } => 1.0,
+            TextureDesc::Checker { scale, .. }
+            | TextureDesc::Noise { scale, .. }
+            | TextureDesc::Marble { scale, .. }
+            | TextureDesc::Wood { scale, .. }
+            | TextureDesc::Clouds { scale, .. } => *scale,
+        };
+        if scale <= 0.0 {
+            return Err(self.invalid(format!("texture '{}': scale must be positive, got {}", name, scale)));
+        }
+
+        let texture: Arc<dyn Texture> = match desc {
+            TextureDesc::Solid { color } => Arc::new(SolidColor::new(vec3(*color))),
+            TextureDesc::Checker { scale, even, odd } => Arc::new(CheckerTexture::from_colors(*scale, vec3(*even), vec3(*odd))),
+            TextureDesc::Image { path, wrap } => {
+                let wrap = match wrap {
+                    WrapDesc::Repeat => WrapMode::Repeat,
+                    WrapDesc::Clamp => WrapMode::Clamp,
+                    WrapDesc::Mirror => WrapMode::Mirror,
+                };
+                let image = ImageTexture::load(self.resolve(path))
+                    .map_err(|e| SceneError::Texture { path: self.path.to_path_buf(), source: e })?;
+                Arc::new(image.with_wrap(wrap))
+            }
+            TextureDesc::Noise { scale, seed } => Arc::new(NoiseTexture::new(*seed, *scale)),
+            TextureDesc::Marble { scale, base, vein, seed } => {
+                Arc::new(MarbleTexture::new(*seed, *scale, vec3(*base), vec3(*vein)))
+            }
+            TextureDesc::Wood { scale, light, dark, seed } => {
+                Arc::new(WoodTexture::new(*seed, *scale, vec3(*light), vec3(*dark)))
+            }
+            TextureDesc::Clouds { scale, sky, cloud, seed } => {
+                Arc::new(CloudTexture::new(*seed, *scale, vec3(*sky), vec3(*cloud)))
+            }
+        };
+        return Ok(texture);
+    }
+
+    fn albedo(&self, name: &str, desc: &AlbedoDesc) -> Result<Arc<dyn Texture>, SceneError> {
+        match desc {
+            AlbedoDesc::Color(color) => Ok(Arc::new(SolidColor::new(vec3(*color)))),
+            AlbedoDesc::Texture(texture) => match self.textures.get(texture) {
+                Some(texture) => Ok(texture.clone()),
+                None => Err(self.invalid(format!("material '{}': unknown texture '{}'", name, texture))),
+            },
+        }
+    }
+
+    fn material(&self, name: &str, desc: &MaterialDesc) -> Result<Arc<dyn Material>, SceneError> {
+        let material: Arc<dyn Material> = match *desc {
+            MaterialDesc::Lambertian { ref albedo, vertex_colors } => {
+                match albedo {
+                    AlbedoDesc::Color(color) if vertex_colors => Arc::new(Lambertian::vertex_colored(vec3(*color))),
+                    AlbedoDesc::Texture(_) if vertex_colors => {
+                        return Err(self.invalid(format!("material '{}': vertex_colors needs a constant albedo", name)));
+                    }
+                    _ => Arc::new(Lambertian::from_texture(self.albedo(name, albedo)?)),
+                }
+            }
+            MaterialDesc::Metal { ref albedo, fuzz } => {
+                if !(0.0..=1.0).contains(&fuzz) {
+                    return Err(self.invalid(format!("material '{}': fuzz must be between 0 and 1, got {}", name, fuzz)));
+                }
+                Arc::new(Metal::from_texture(self.albedo(name, albedo)?, fuzz))
+            }
+            MaterialDesc::Dielectric { refraction_index } => {
+                if refraction_index <= 0.0 {
+                    return Err(self.invalid(format!("material '{}': refraction_index must be positive, got {}", name, refraction_index)));
+                }
+                Arc::new(Dielectric::new(refraction_index))
+            }
+            MaterialDesc::DiffuseLight { ref emit } => Arc::new(DiffuseLight::from_texture(self.albedo(name, emit)?)),
+        };
+        return Ok(material);
+    }
+
+    fn lookup(&self, index: usize, name: &str) -> Result<Arc<dyn Material>, SceneError> {
+        match self.materials.get(name) {
+            Some(material) => Ok(material.clone()),
+            None => Err(self.invalid(format!("object {}: unknown material '{}'", index, name))),
+        }
+    }
+
+    fn primitive(
+        &self,
+        index: usize,
+        material: &str,
+        area: f64,
+        world: &mut HittableList,
+        lights: &mut Vec<(Box<dyn Hittable>, f64)>,
+        make: impl Fn(Arc<dyn Material>) -> Box<dyn Hittable>,
+    ) -> Result<(), SceneError> {
+        let material_ref = self.lookup(index, material)?;
+        if self.light_materials.contains(material) {
+            // Emitters go into the world to be seen, and into the lights to be sampled
+            let light = make(material_ref.clone());
+            let power = light_power(material_ref.as_ref(), area, light.bounding_box().centroid());
+            lights.push((light, power));
+        }
+        world.add(make(material_ref));
+        return Ok(());
+    }
+
+    fn object(
+        &self,
+        index: usize,
+        desc: &ObjectDesc,
+        world: &mut HittableList,
+        lights: &mut Vec<(Box<dyn Hittable>, f64)>,
+        warnings: &mut Vec<String>,
+        imported_camera: &mut Option<GltfCamera>,
+    ) -> Result<(), SceneError> {
+        match desc {
+            ObjectDesc::Sphere { center, radius, material } => {
+                if *radius <= 0.0 {
+                    return Err(self.invalid(format!("object {} (sphere): radius must be positive, got {}", index, radius)));
+                }
+                let area = 4.0 * common::PI * radius * radius;
+                self.primitive(index, material, area, world, lights, |m| Box::new(Sphere::new(vec3(*center), *radius, m)))?;
+            }
+            ObjectDesc::Triangle { vertices, material } => {
+                let area = 0.5 * cross(vec3(vertices[1]) - vec3(vertices[0]), vec3(vertices[2]) - vec3(vertices[0])).length();
+                self.primitive(index, material, area, world, lights, |m| {
+                    Box::new(Triangle::new(vec3(vertices[0]), vec3(vertices[1]), vec3(vertices[2]), m))
+                })?;
+            }
+            ObjectDesc::Quad { q, u, v, material } => {
+                if cross(vec3(*u), vec3(*v)).near_zero() {
+                    return Err(self.invalid(format!("object {} (quad): edges u and v must not be parallel", index)));
+                }
+                let area = cross(vec3(*u), vec3(*v)).length();
+                self.primitive(index, material, area, world, lights, |m| Box::new(Quad::new(vec3(*q), vec3(*u), vec3(*v), m)))?;
+            }
+            ObjectDesc::Box { min, max, rotate_y, material } => {
+                if (0..3).any(|axis| min[axis] >= max[axis]) {
+                    return Err(self.invalid(format!("object {} (box): min must be below max on every axis", index)));
+                }
+                let size = vec3(*max) - vec3(*min);
+                let area = 2.0 * (size.x * size.y + size.y * size.z + size.z * size.x);
+                self.primitive(index, material, area, world, lights, |m| {
+                    Box::new(quad::make_rotated_box(vec3(*min), vec3(*max), *rotate_y, m))
+                })?;
+            }
+            ObjectDesc::Mesh { path, material } => {
+                let mesh_path = self.resolve(path);
+                let extension = mesh_path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .map(|e| e.to_ascii_lowercase())
+                    .unwrap_or_default();
+                let mesh_error = |e: Box<dyn Error + Send + Sync>| SceneError::Mesh { path: self.path.to_path_buf(), source: e };
+
+                match extension.as_str() {
+                    "ply" => {
+                        let material = match material {
+                            Some(name) => Some(self.lookup(index, name)?),
+                            None => None,
+                        };
+                        let mesh = ply::load_ply(&mesh_path, material).map_err(|e| mesh_error(e.into()))?;
+                        mesh_lights(&mesh, lights);
+                        world.add(Box::new(mesh));
+                    }
+                    "obj" | "gltf" | "glb" => {
+                        if material.is_some() {
+                            return Err(self.invalid(format!(
+                                "object {} (mesh): '{}' brings its own materials, material overrides only apply to PLY meshes",
+                                index, path.display())));
+                        }
+                        let meshes = if extension == "obj" {
+                            let obj = obj::load_obj(&mesh_path).map_err(|e| mesh_error(e.into()))?;
+                            warnings.extend(obj.warnings);
+                            obj.meshes
+                        } else {
+                            let gltf = gltf_import::load_gltf(&mesh_path).map_err(|e| mesh_error(e.into()))?;
+                            if imported_camera.is_none() {
+                                *imported_camera = gltf.camera;
+                            }
+                            gltf.meshes
+                        };
+                        for mesh in &meshes {
+                            mesh_lights(mesh, lights);
+                        }
+                        if !meshes.is_empty() {
+                            let meshes = meshes.into_iter().map(|mesh| Box::new(mesh) as Box<dyn Hittable>).collect();
+                            world.add(Box::new(LinearBvh::from_objects(meshes)));
+                        }
+                    }
+                    _ => {
+                        return Err(self.invalid(format!(
+                            "object {} (mesh): unsupported mesh format '{}', expected .obj, .ply, .gltf or .glb",
+                            index, path.display())));
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+
+pub fn load_scene(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+    // Load a JSON scene description. The camera is returned uninitialized, so callers can
+    // still adjust it before calling Camera::initialize
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| SceneError::Io { path: path.to_path_buf(), source: e })?;
+    let file: SceneFile = serde_json::from_str(&source)
+        .map_err(|e| SceneError::Syntax { path: path.to_path_buf(), source: e })?;
+
+    let mut builder = SceneBuilder {
+        path: path,
+        textures: BTreeMap::new(),
+        materials: BTreeMap::new(),
+        light_materials: HashSet::new(),
+    };
+    let mut camera = builder.camera(file.camera.as_ref().unwrap_or(&CameraDesc::default()))?;
+    let tone_mapping = builder.tone_mapping(&file.tone_mapping)?;
+
+    for (name, desc) in &file.textures {
+        let texture = builder.texture(name, desc)?;
+        builder.textures.insert(name.clone(), texture);
+    }
+
+    for (name, desc) in &file.materials {
+        let material = builder.material(name, desc)?;
+        builder.materials.insert(name.clone(), material);
+        if let MaterialDesc::DiffuseLight { .. } = desc {
+            builder.light_materials.insert(name.clone());
+        }
+    }
+
+    let mut world = HittableList::new();
+    let mut lights = Vec::new();
+    let mut warnings = Vec::new();
+    let mut imported_camera = None;
+    for (index, desc) in file.objects.iter().enumerate() {
+        builder.object(index, desc, &mut world, &mut lights, &mut warnings, &mut imported_camera)?;
+    }
+    if let (None, Some(imported)) = (&file.camera, imported_camera) {
+        imported.apply(&mut camera);
+    }
+    let sampling = match file.light_sampling {
+        LightSamplingDesc::Uniform => LightSampling::Uniform,
+        LightSamplingDesc::Power => LightSampling::Power,
+        LightSamplingDesc::Bvh => LightSampling::Bvh,
+    };
+    let punctual_lights = file
+        .lights
+        .iter()
+        .enumerate()
+        .map(|(index, desc)| builder.punctual_light(index, desc))
+        .collect::<Result<Vec<_>, _>>()?;
+    let lights = LightList::new(lights).with_sampling(sampling).with_punctual_lights(punctual_lights);
+
+    return Ok(Scene { camera: camera, world: world, lights: lights, tone_mapping: tone_mapping, warnings: warnings });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_scene(name: &str, source: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("scene_test_{}_{}.json", std::process::id(), name));
+        fs::write(&path, source).unwrap();
+        return path;
+    }
+
+    fn invalid_message(result: Result<Scene, SceneError>) -> String {
+        match result {
+            Err(SceneError::Invalid { message, .. }) => message,
+            Err(e) => panic!("expected an invalid scene error, got {}", e),
+            Ok(_) => panic!("expected an invalid scene error"),
+        }
+    }
+
+    #[test]
+    fn loads_materials_textures_and_lights() {
+        let path = write_scene("valid", r#"{
+            "textures": { "checks": { "type": "checker", "scale": 0.5, "even": [1, 1, 1], "odd": [0, 0, 0] } },
+            "materials": {
+                "floor": { "type": "lambertian", "albedo": "checks" },
+                "lamp": { "type": "diffuse_light", "emit": [4, 4, 4] }
+            },
+            "objects": [
+                { "type": "sphere", "center": [0, -100, 0], "radius": 100, "material": "floor" },
+                { "type": "sphere", "center": [0, 3, 0], "radius": 1, "material": "lamp" }
+            ]
+        }"#);
+        let scene = load_scene(path).unwrap();
+        assert_eq!(scene.world.objects.len(), 2);
+        assert_eq!(scene.lights.len(), 1);
+        assert!(scene.warnings.is_empty());
+    }
+
+    #[test]
+    fn collects_emissive_obj_triangles_as_lights() {
+        let dir = std::env::temp_dir().join(format!("scene_test_{}_obj_light", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lamp.mtl"), "newmtl lamp\nKe 5 5 5\nnewmtl grey\nKd 0.5 0.5 0.5\n").unwrap();
+        fs::write(dir.join("lamp.obj"), "mtllib lamp.mtl\n\
+v 0 1 0\nv 1 1 0\nv 1 1 1\nv 0 1 1\nv 0 0 0\nv 1 0 0\nv 1 0 1\n\
+usemtl lamp\nf 1 2 3 4\nusemtl grey\nf 5 6 7\n").unwrap();
+        let path = dir.join("scene.json");
+        fs::write(&path, r#"{ "objects": [ { "type": "mesh", "path": "lamp.obj" } ] }"#).unwrap();
+
+        // Both triangles of the emissive quad, but not the grey triangle
+        let scene = load_scene(path).unwrap();
+        assert_eq!(scene.lights.len(), 2);
+    }
+
+    #[test]
+    fn rejects_unknown_material_names() {
+        let path = write_scene("unknown_material", r#"{
+            "materials": { "grey": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] } },
+            "objects": [
+                { "type": "sphere", "center": [0, 0, -1], "radius": 0.5, "material": "grey" },
+                { "type": "sphere", "center": [0, 1, -1], "radius": 0.5, "material": "gray" }
+            ]
+        }"#);
+        assert_eq!(invalid_message(load_scene(path)), "object 1: unknown material 'gray'");
+    }
+
+    #[test]
+    fn rejects_unknown_texture_names() {
+        let path = write_scene("unknown_texture", r#"{
+            "textures": { "marble": { "type": "marble", "scale": 4, "base": [1, 1, 1], "vein": [0, 0, 0] } },
+            "materials": { "stone": { "type": "metal", "albedo": "marbel", "fuzz": 0.1 } }
+        }"#);
+        assert_eq!(invalid_message(load_scene(path)), "material 'stone': unknown texture 'marbel'");
+    }
+
+    #[test]
+    fn reports_the_first_bad_material_by_name() {
+        // Materials are built in name order, so the same file always reports the same error
+        let path = write_scene("two_bad_materials", r#"{
+            "materials": {
+                "zinc": { "type": "metal", "albedo": "missing", "fuzz": 0.1 },
+                "brass": { "type": "metal", "albedo": "absent", "fuzz": 0.1 }
+            }
+        }"#);
+        assert_eq!(invalid_message(load_scene(path)), "material 'brass': unknown texture 'absent'");
+    }
+
+    #[test]
+    fn rejects_spheres_without_a_positive_radius() {
+        let path = write_scene("negative_radius", r#"{
+            "materials": { "grey": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] } },
+            "objects": [ { "type": "sphere", "center": [0, 0, -1], "radius": -0.5, "material": "grey" } ]
+        }"#);
+        assert_eq!(invalid_message(load_scene(path)), "object 0 (sphere): radius must be positive, got -0.5");
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let path = write_scene("syntax", r#"{ "objects": [ { "type": "sphere", "centre": [0, 0, 0] } ] }"#);
+        assert!(matches!(load_scene(path), Err(SceneError::Syntax { .. })));
+    }
+}
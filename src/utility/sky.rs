@@ -0,0 +1,253 @@
+use crate::utility::colors::Color;
+use crate::utility::common;
+use crate::utility::onb::Onb;
+use crate::utility::vec3::{self, Vec3};
+
+
+const SUN_ANGULAR_RADIUS: f64 = 0.004_65; // Radians, as seen from the earth
+
+// The model gives luminance in kcd/m², with a clear daytime zenith of roughly 5 to 20. This is
+// an exposure choice rather than a unit conversion: it brings the sky to radiance around one,
+// the brightness of the default gradient background and of typical scene lights
+const LUMINANCE_SCALE: f64 = 0.1;
+
+// Luminance of the solar disk before atmospheric extinction, the commonly quoted 1.6e9 cd/m²
+// in the model's kcd/m². Scaled and dimmed by the atmosphere, the sun stays tens of thousands
+// of times brighter than the sky around it, which is why it is sampled directly
+const SUN_LUMINANCE: f64 = 1.6e6;
+
+
+// Analytic daylight sky after Preetham, Shirley and Smits, "A Practical Analytic Model for
+// Daylight" (1999), with a sun disk dimmed by the same atmosphere. The ground below the
+// horizon is a diffuse plane lit by the sun and sky
+pub struct PhysicalSky {
+    sun_direction: Vec3,
+    theta_sun: f64, // Angle between the sun and the zenith
+    intensity: f64,
+    zenith: (f64, f64, f64), // Luminance and chromaticity (Y, x, y) at the zenith
+    perez_y: [f64; 5], // Perez distribution coefficients A to E for Y, x and y
+    perez_x_chroma: [f64; 5],
+    perez_y_chroma: [f64; 5],
+    sun_radiance: Color,
+    ground_radiance: Color,
+}
+
+impl PhysicalSky {
+    pub fn new(sun_elevation: f64, sun_azimuth: f64, turbidity: f64, ground_albedo: Color) -> Self {
+        // Elevation is in degrees above the horizon. Azimuth is in degrees from -z, the default
+        // camera direction, turning towards +x. The model holds for turbidities of about 2
+        // (very clear) to 10 (hazy), and for the sun above the horizon
+        let elevation = common::degrees_to_radians(sun_elevation.clamp(0.0, 90.0));
+        let azimuth = common::degrees_to_radians(sun_azimuth);
+        let t = turbidity.clamp(1.7, 10.0);
+        let theta_sun = common::PI / 2.0 - elevation;
+        let sun_direction = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+
+        // Zenith luminance, then zenith chromaticity as cubic fits in the sun angle
+        let chi = (4.0 / 9.0 - t / 120.0) * (common::PI - 2.0 * theta_sun);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let (th, th2, th3) = (theta_sun, theta_sun * theta_sun, theta_sun * theta_sun * theta_sun);
+        let zenith_x = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_y = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        let perez_y = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let perez_x_chroma = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let perez_y_chroma = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        let mut sky = PhysicalSky {
+            sun_direction: sun_direction,
+            theta_sun: theta_sun,
+            intensity: 1.0,
+            zenith: (zenith_luminance, zenith_x, zenith_y),
+            perez_y: perez_y,
+            perez_x_chroma: perez_x_chroma,
+            perez_y_chroma: perez_y_chroma,
+            sun_radiance: Self::sun_radiance(theta_sun, t),
+            ground_radiance: Color::default(),
+        };
+
+        // Light the ground with the sun and a zenith-bright sky. Rays below the horizon see a
+        // diffuse plane, so its radiance is albedo / pi times the irradiance
+        let sun_solid_angle = 2.0 * common::PI * (1.0 - SUN_ANGULAR_RADIUS.cos());
+        let sun_irradiance = sun_solid_angle * elevation.sin() * sky.sun_radiance;
+        let sky_irradiance = common::PI * sky.sky_radiance(Vec3::new(0.0, 1.0, 0.0));
+        sky.ground_radiance = (1.0 / common::PI) * ground_albedo * (sun_irradiance + sky_irradiance);
+        return sky;
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        return self;
+    }
+
+    pub fn value(&self, direction: Vec3) -> Color {
+        let d = vec3::unit_vector(direction);
+        if d.y < 0.0 {
+            return self.intensity * self.ground_radiance;
+        }
+        if self.in_sun_disk(d) {
+            return self.intensity * (self.sun_radiance + self.sky_radiance(d));
+        }
+        return self.intensity * self.sky_radiance(d);
+    }
+
+    pub fn sample(&self) -> Option<(Vec3, f64)> {
+        // Pick a direction uniformly within the sun disk. The rest of the sky is smooth
+        // enough to be left to scattered rays
+        if self.sun_direction.y <= 0.0 {
+            return None;
+        }
+        let cos_max = SUN_ANGULAR_RADIUS.cos();
+        let cos_theta = 1.0 - common::random_double() * (1.0 - cos_max);
+        let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
+        let phi = 2.0 * common::PI * common::random_double();
+
+        let uvw = Onb::new(self.sun_direction);
+        let direction = uvw.transform(Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+        return Some((direction, 1.0 / (2.0 * common::PI * (1.0 - cos_max))));
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        // Uniform over the cone of the sun disk, and zero outside it
+        let d = vec3::unit_vector(direction);
+        if self.sun_direction.y <= 0.0 || !self.in_sun_disk(d) {
+            return 0.0;
+        }
+        return 1.0 / (2.0 * common::PI * (1.0 - SUN_ANGULAR_RADIUS.cos()));
+    }
+
+    fn in_sun_disk(&self, unit_direction: Vec3) -> bool {
+        return vec3::dot(unit_direction, self.sun_direction) >= SUN_ANGULAR_RADIUS.cos();
+    }
+
+    fn sky_radiance(&self, unit_direction: Vec3) -> Color {
+        // Perez distribution relative to the zenith, for luminance and both chromaticities
+        let cos_theta = unit_direction.y.max(0.01);
+        let gamma = f64::acos(vec3::dot(unit_direction, self.sun_direction).clamp(-1.0, 1.0));
+        let relative = |coefficients: &[f64; 5]| {
+            Self::perez(coefficients, cos_theta, gamma) / Self::perez(coefficients, 1.0, self.theta_sun)
+        };
+
+        let (zenith_luminance, zenith_x, zenith_y) = self.zenith;
+        let luminance = zenith_luminance * relative(&self.perez_y);
+        let x = zenith_x * relative(&self.perez_x_chroma);
+        let y = zenith_y * relative(&self.perez_y_chroma);
+        return LUMINANCE_SCALE * xyy_to_rgb(x, y, luminance);
+    }
+
+    fn perez(coefficients: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = *coefficients;
+        let cos_gamma = gamma.cos();
+        return (1.0 + a * f64::exp(b / cos_theta)) * (1.0 + c * f64::exp(d * gamma) + e * cos_gamma * cos_gamma);
+    }
+
+    fn sun_radiance(theta_sun: f64, turbidity: f64) -> Color {
+        // Sunlight after Rayleigh and aerosol extinction along the optical air mass, evaluated
+        // at representative wavelengths (in micrometers) for red, green and blue
+        let theta_degrees = theta_sun.to_degrees();
+        let air_mass = 1.0 / (theta_sun.cos() + 0.15 * f64::powf(93.885 - theta_degrees, -1.253));
+        let beta = 0.04608 * turbidity - 0.04586;
+        let transmittance = |lambda: f64| {
+            let rayleigh = f64::exp(-0.008735 * f64::powf(lambda, -4.08) * air_mass);
+            let aerosol = f64::exp(-beta * f64::powf(lambda, -1.3) * air_mass);
+            rayleigh * aerosol
+        };
+        let color = Color::new(transmittance(0.65), transmittance(0.57), transmittance(0.475));
+        return LUMINANCE_SCALE * SUN_LUMINANCE * color;
+    }
+}
+
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    // CIE xyY to linear sRGB
+    if y <= 0.0 {
+        return Color::default();
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    let r = 3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z;
+    let g = -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z;
+    let b = 0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z;
+    return Color::new(r.max(0.0), g.max(0.0), b.max(0.0));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::colors;
+
+    fn sky(elevation: f64) -> PhysicalSky {
+        return PhysicalSky::new(elevation, 30.0, 3.0, Color::from_float(0.3));
+    }
+
+    #[test]
+    fn sun_samples_carry_the_density_of_pdf() {
+        common::seed_rng(4);
+        let sky = sky(35.0);
+        for _ in 0..1000 {
+            let (direction, pdf) = sky.sample().expect("the sun is up");
+            assert!((sky.pdf(direction) - pdf).abs() <= 1e-9 * pdf);
+            // Sampled directions see the sun disk on top of the sky
+            assert!(sky.value(direction).x > 1000.0 * sky.sky_radiance(vec3::unit_vector(direction)).x);
+        }
+        assert_eq!(sky.pdf(Vec3::new(0.0, 1.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn sun_on_or_below_the_horizon_is_not_sampled() {
+        common::seed_rng(4);
+        for elevation in [0.0, -20.0] {
+            let sky = sky(elevation);
+            assert!(sky.sample().is_none());
+            assert_eq!(sky.pdf(sky.sun_direction), 0.0);
+        }
+    }
+
+    #[test]
+    fn sky_radiance_stays_positive() {
+        for elevation in [0.0, 5.0, 30.0, 60.0, 90.0] {
+            for turbidity in [2.0, 5.0, 10.0] {
+                let sky = PhysicalSky::new(elevation, 120.0, turbidity, Color::from_float(0.3));
+                // Directions over the whole sphere, from the nadir through the horizon to the zenith
+                for j in 0..=20 {
+                    let y = -1.0 + 0.1 * j as f64;
+                    let r = f64::sqrt(1.0 - y * y);
+                    for i in 0..16 {
+                        let phi = 2.0 * common::PI * i as f64 / 16.0;
+                        let c = sky.value(Vec3::new(r * phi.cos(), y, r * phi.sin()));
+                        assert!(c.x.is_finite() && c.y.is_finite() && c.z.is_finite());
+                        assert!(c.x >= 0.0 && c.y >= 0.0 && c.z >= 0.0, "{:?}", c);
+                        assert!(colors::luminance(c) > 0.0, "elevation {}, turbidity {}, y {}", elevation, turbidity, y);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -5,24 +5,42 @@ use crate::utility::material::Material;
 use crate::utility::ray::Ray;
 use crate::utility::vec3::{self,Point3, Vec3};
 use crate::utility::interval::Interval;
+use crate::utility::aabb::Aabb;
+use crate::utility::common;
+use crate::utility::onb::Onb;
 
 
 pub struct Sphere {
     center: Point3,
     radius: f64,
     material: Arc<dyn Material>,
+    bbox: Aabb,
 }
 
 
 impl Sphere {
     pub fn new(center: Point3, radius: f64, material: Arc<dyn Material>) -> Self {
+        let radius = f64::max(0.0, radius);
+        let rvec = Vec3::from_float(radius);
         Sphere {
             center: center,
             radius: radius,
             material: material,
-
+            bbox: Aabb::from_points(center - rvec, center + rvec),
         }
     }
+
+    fn get_sphere_uv(p: Point3) -> (f64, f64) {
+        // p: a given point on the sphere of radius one, centered at the origin
+        // u: returned value [0,1] of angle around the Y axis from X=-1
+        // v: returned value [0,1] of angle from Y=-1 to Y=+1
+        //     <1 0 0> yields <0.50 0.50>       <-1  0  0> yields <0.00 0.50>
+        //     <0 1 0> yields <0.50 1.00>       < 0 -1  0> yields <0.50 0.00>
+        //     <0 0 1> yields <0.25 0.50>       < 0  0 -1> yields <0.75 0.50>
+        let theta = f64::acos(f64::clamp(-p.y, -1.0, 1.0));
+        let phi = f64::atan2(-p.z, p.x) + common::PI;
+        return (phi / (2.0 * common::PI), theta / common::PI);
+    }
 }
 
 
@@ -54,13 +72,42 @@ impl Hittable for Sphere {
             p: r.at(root),
             mat: self.material.clone(),
             normal: Default::default(),
+            u: 0.0,
+            v: 0.0,
+            barycentric: (0.0, 0.0),
+            color: None,
+            object_id: 0,
             front_face: Default::default(),
         };
 
         let outward_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(r, outward_normal);
+        (rec.u, rec.v) = Self::get_sphere_uv(outward_normal);
         return Some(rec);
     }
 
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        // This method only works for stationary spheres
+        if self.hit(&Ray::new(origin, direction), Interval::new(0.001, common::INFINITY)).is_none() {
+            return 0.0;
+        }
+
+        let dist_squared = (self.center - origin).length_squared();
+        let cos_theta_max = f64::sqrt(1.0 - self.radius * self.radius / dist_squared);
+        let solid_angle = 2.0 * common::PI * (1.0 - cos_theta_max);
+
+        return 1.0 / solid_angle;
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+        let uvw = Onb::new(direction);
+        return uvw.transform(vec3::random_to_sphere(self.radius, distance_squared));
+    }
 }
 
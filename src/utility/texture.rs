@@ -0,0 +1,389 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::ColorType;
+
+use crate::utility::colors::{self, Color};
+use crate::utility::perlin::Perlin;
+use crate::utility::vec3::Point3;
+
+
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> Self {
+        SolidColor { albedo: albedo }
+    }
+
+    pub fn from_rgb(red: f64, green: f64, blue: f64) -> Self {
+        SolidColor::new(Color::new(red, green, blue))
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        return self.albedo;
+    }
+}
+
+
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+        CheckerTexture { inv_scale: 1.0 / scale, even: even, odd: odd }
+    }
+
+    pub fn from_colors(scale: f64, c1: Color, c2: Color) -> Self {
+        CheckerTexture::new(scale, Arc::new(SolidColor::new(c1)), Arc::new(SolidColor::new(c2)))
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        // A solid checker pattern: space is split into cubes of side scale, alternating
+        // between the two textures
+        let x_integer = f64::floor(self.inv_scale * p.x) as i64;
+        let y_integer = f64::floor(self.inv_scale * p.y) as i64;
+        let z_integer = f64::floor(self.inv_scale * p.z) as i64;
+
+        let is_even = (x_integer + y_integer + z_integer) % 2 == 0;
+        return if is_even { self.even.value(u, v, p) } else { self.odd.value(u, v, p) };
+    }
+}
+
+
+pub struct NoiseTexture {
+    noise: Perlin,
+    scale: f64,
+}
+
+impl NoiseTexture {
+    pub fn new(seed: u64, scale: f64) -> Self {
+        NoiseTexture { noise: Perlin::new(seed), scale: scale }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        // Plain gradient noise, shifted from [-1,1] to a gray in [0,1]
+        return Color::from_float(0.5 * (1.0 + self.noise.noise(&(self.scale * *p))));
+    }
+}
+
+
+pub struct MarbleTexture {
+    noise: Perlin,
+    scale: f64,
+    base: Color,
+    vein: Color,
+}
+
+impl MarbleTexture {
+    pub fn new(seed: u64, scale: f64, base: Color, vein: Color) -> Self {
+        MarbleTexture { noise: Perlin::new(seed), scale: scale, base: base, vein: vein }
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        // Regular stripes along z, with their phase disturbed by turbulence
+        let t = 0.5 * (1.0 + f64::sin(self.scale * p.z + 10.0 * self.noise.turbulence(p, 7)));
+        return t * self.base + (1.0 - t) * self.vein;
+    }
+}
+
+
+pub struct WoodTexture {
+    noise: Perlin,
+    scale: f64,
+    light: Color,
+    dark: Color,
+}
+
+impl WoodTexture {
+    pub fn new(seed: u64, scale: f64, light: Color, dark: Color) -> Self {
+        WoodTexture { noise: Perlin::new(seed), scale: scale, light: light, dark: dark }
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        // Growth rings around the y axis, made irregular by low frequency noise, with a
+        // sharp dark edge at the end of each ring
+        let distance = self.scale * f64::hypot(p.x, p.z);
+        let grain = distance + 0.8 * self.noise.fbm(&(0.5 * self.scale * *p), 3, 2.0, 0.5);
+        let ring = f64::powi(grain - grain.floor(), 3);
+        return (1.0 - ring) * self.light + ring * self.dark;
+    }
+}
+
+
+pub struct CloudTexture {
+    noise: Perlin,
+    scale: f64,
+    sky: Color,
+    cloud: Color,
+}
+
+impl CloudTexture {
+    pub fn new(seed: u64, scale: f64, sky: Color, cloud: Color) -> Self {
+        CloudTexture { noise: Perlin::new(seed), scale: scale, sky: sky, cloud: cloud }
+    }
+}
+
+impl Texture for CloudTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        // Six octaves of fBm as a density, smoothly thresholded so clear sky shows between
+        // the clouds
+        let density = 0.5 + self.noise.fbm(&(self.scale * *p), 6, 2.0, 0.5);
+        let t = f64::clamp(density, 0.0, 1.0);
+        let t = t * t * (3.0 - 2.0 * t);
+        return (1.0 - t) * self.sky + t * self.cloud;
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WrapMode {
+    #[default]
+    Repeat, // Tile the image
+    Clamp,  // Stretch the edge texels
+    Mirror, // Tile the image, flipping every other copy
+}
+
+impl WrapMode {
+    fn apply(&self, i: i64, n: i64) -> usize {
+        // Map a texel coordinate of any value into [0, n)
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let period = i.rem_euclid(2 * n);
+                if period < n { period } else { 2 * n - 1 - period }
+            }
+        };
+        return i as usize;
+    }
+}
+
+
+#[derive(Debug)]
+pub struct TextureError {
+    pub path: PathBuf,
+    pub source: image::ImageError,
+}
+
+impl Display for TextureError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.source)
+    }
+}
+
+impl Error for TextureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+
+// The pixels of an image as linear colors, row by row from the top. Image textures and
+// environment maps share its decoding and filtering
+pub struct LinearImage {
+    width: usize,
+    height: usize,
+    texels: Vec<Color>,
+}
+
+impl LinearImage {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TextureError> {
+        // Radiance HDR and OpenEXR files hold linear values already. Other formats such as PNG
+        // and JPEG are assumed to be sRGB encoded, and are linearized once here rather than on
+        // every lookup
+        let path = path.as_ref();
+        let image = image::open(path).map_err(|e| TextureError { path: path.to_path_buf(), source: e })?;
+        let (width, height) = (image.width() as usize, image.height() as usize);
+
+        let texels = if matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F) {
+            image
+                .into_rgb32f()
+                .pixels()
+                .map(|pixel| Color::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64))
+                .collect()
+        } else {
+            image
+                .into_rgb8()
+                .pixels()
+                .map(|pixel| {
+                    let channel = |c: u8| colors::srgb_to_linear(c as f64 / 255.0);
+                    Color::new(channel(pixel[0]), channel(pixel[1]), channel(pixel[2]))
+                })
+                .collect()
+        };
+        return Ok(LinearImage::from_texels(width, height, texels));
+    }
+
+    pub fn from_texels(width: usize, height: usize, texels: Vec<Color>) -> Self {
+        assert_eq!(texels.len(), width * height, "texel count doesn't match the image size");
+        assert!(width > 0 && height > 0, "images can't be empty");
+        LinearImage { width: width, height: height, texels: texels }
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    pub fn texels(&self) -> &[Color] {
+        return &self.texels;
+    }
+
+    pub fn bilinear(&self, x: f64, y: f64, wrap_x: WrapMode, wrap_y: WrapMode) -> Color {
+        // Bilinear filtering between the four texels around (x, y), given as fractions of the
+        // image size from its top left corner. Texel centers sit at half integer coordinates
+        let x = x * self.width as f64 - 0.5;
+        let y = y * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (i0, j0) = (x0 as i64, y0 as i64);
+
+        let (width, height) = (self.width as i64, self.height as i64);
+        let (i1, i2) = (wrap_x.apply(i0, width), wrap_x.apply(i0 + 1, width));
+        let (j1, j2) = (wrap_y.apply(j0, height), wrap_y.apply(j0 + 1, height));
+        let texel = |i: usize, j: usize| self.texels[j * self.width + i];
+
+        let top = (1.0 - tx) * texel(i1, j1) + tx * texel(i2, j1);
+        let bottom = (1.0 - tx) * texel(i1, j2) + tx * texel(i2, j2);
+        return (1.0 - ty) * top + ty * bottom;
+    }
+}
+
+
+pub struct ImageTexture {
+    image: LinearImage,
+    wrap: WrapMode,
+}
+
+impl ImageTexture {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TextureError> {
+        return Ok(ImageTexture::from_image(LinearImage::load(path)?));
+    }
+
+    pub fn from_texels(width: usize, height: usize, texels: Vec<Color>) -> Self {
+        ImageTexture::from_image(LinearImage::from_texels(width, height, texels))
+    }
+
+    pub fn from_image(image: LinearImage) -> Self {
+        ImageTexture { image: image, wrap: WrapMode::default() }
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        return self;
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        // v is flipped since image rows go top to bottom
+        return self.image.bilinear(u, 1.0 - v, self.wrap, self.wrap);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(x: f64) -> Color {
+        return Color::from_float(x);
+    }
+
+    fn ramp() -> LinearImage {
+        // One row of four texels with values 0 to 3
+        return LinearImage::from_texels(4, 1, (0..4).map(|i| grey(i as f64)).collect());
+    }
+
+    fn at(image: &LinearImage, x: f64, wrap: WrapMode) -> f64 {
+        return image.bilinear(x, 0.5, wrap, WrapMode::Clamp).x;
+    }
+
+    #[test]
+    fn checker_alternates_across_the_origin() {
+        let checker = CheckerTexture::from_colors(2.0, grey(0.0), grey(1.0));
+        let value = |x: f64, y: f64, z: f64| checker.value(0.0, 0.0, &Point3::new(x, y, z)).x;
+        assert_eq!(value(1.0, 1.0, 1.0), 0.0);
+        assert_eq!(value(3.0, 1.0, 1.0), 1.0);
+        assert_eq!(value(3.0, 3.0, 1.0), 0.0);
+        // Cells just below zero belong to the next cube, not the one at the origin
+        assert_eq!(value(-1.0, 1.0, 1.0), 1.0);
+        assert_eq!(value(-1.0, -1.0, 1.0), 0.0);
+        assert_eq!(value(-0.001, -0.001, -0.001), 1.0);
+    }
+
+    #[test]
+    fn bilinear_hits_texel_centers_and_blends_between_them() {
+        let image = ramp();
+        for i in 0..4 {
+            assert!((at(&image, (i as f64 + 0.5) / 4.0, WrapMode::Clamp) - i as f64).abs() < 1e-12);
+        }
+        assert!((at(&image, 0.25, WrapMode::Clamp) - 0.5).abs() < 1e-12);
+        assert!((at(&image, 0.5, WrapMode::Clamp) - 1.5).abs() < 1e-12);
+        assert!((at(&image, 0.5 + 0.25 / 4.0, WrapMode::Clamp) - 1.75).abs() < 1e-12);
+
+        // Both axes at once, between the four texels of a 2x2 image
+        let image = LinearImage::from_texels(2, 2, vec![grey(0.0), grey(1.0), grey(2.0), grey(4.0)]);
+        let center = image.bilinear(0.5, 0.5, WrapMode::Clamp, WrapMode::Clamp).x;
+        assert!((center - 1.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn edge_texels_depend_on_the_wrap_mode() {
+        // At the left edge, half way between the first texel and the one beyond the edge
+        let image = ramp();
+        assert!((at(&image, 0.0, WrapMode::Clamp) - 0.0).abs() < 1e-12);
+        assert!((at(&image, 0.0, WrapMode::Mirror) - 0.0).abs() < 1e-12);
+        assert!((at(&image, 0.0, WrapMode::Repeat) - 1.5).abs() < 1e-12);
+        assert!((at(&image, 1.0, WrapMode::Clamp) - 3.0).abs() < 1e-12);
+        assert!((at(&image, 1.0, WrapMode::Repeat) - 1.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn wrap_modes_map_texel_coordinates_into_the_image() {
+        let wrap = |mode: WrapMode| (-5..9).map(|i| mode.apply(i, 4)).collect::<Vec<_>>();
+        assert_eq!(wrap(WrapMode::Repeat), [3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0]);
+        assert_eq!(wrap(WrapMode::Clamp), [0, 0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3, 3]);
+        assert_eq!(wrap(WrapMode::Mirror), [3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0]);
+
+        // Outside [0,1], a texture repeats, stretches its edge or flips
+        let image = ramp();
+        let x = 1.0 + 0.375; // Over the edge by the center of texel 1
+        assert!((at(&image, x, WrapMode::Repeat) - 1.0).abs() < 1e-12);
+        assert!((at(&image, x, WrapMode::Clamp) - 3.0).abs() < 1e-12);
+        assert!((at(&image, x, WrapMode::Mirror) - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn image_texture_puts_v_zero_at_the_bottom_row() {
+        let texture = ImageTexture::from_texels(1, 2, vec![grey(1.0), grey(0.0)]).with_wrap(WrapMode::Clamp);
+        let p = Point3::default();
+        assert_eq!(texture.value(0.5, 0.0, &p).x, 0.0);
+        assert_eq!(texture.value(0.5, 1.0, &p).x, 1.0);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::utility::colors::{luminance, Color};
+use crate::utility::vec3;
+
+
+// Curve compressing linear scene radiance into the [0,1] range of a display
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapOperator {
+    Clamp, // Cut off everything above 1, the classic behaviour
+    Reinhard, // c / (1 + c) per channel
+    ReinhardExtended { white_point: f64 }, // Reinhard on luminance, with the luminance mapped to white
+    Aces, // Stephen Hill's fit of the ACES reference rendering and sRGB output transforms
+    AgX, // Polynomial approximation of Troy Sobotka's AgX
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    pub exposure: f64, // Exposure adjustment in stops, applied before the operator
+    pub operator: ToneMapOperator,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping { exposure: 0.0, operator: ToneMapOperator::Clamp }
+    }
+}
+
+impl ToneMapping {
+    pub fn new(exposure: f64, operator: ToneMapOperator) -> Self {
+        ToneMapping { exposure: exposure, operator: operator }
+    }
+
+    pub fn apply(&self, radiance: Color) -> Color {
+        // Map linear radiance to linear display values in [0,1]. The display transfer function
+        // is applied separately when encoding
+        let c = f64::powf(2.0, self.exposure) * radiance;
+        let mapped = match self.operator {
+            ToneMapOperator::Clamp => c,
+            ToneMapOperator::Reinhard => Color::new(c.x / (1.0 + c.x), c.y / (1.0 + c.y), c.z / (1.0 + c.z)),
+            ToneMapOperator::ReinhardExtended { white_point } => reinhard_extended(c, white_point),
+            ToneMapOperator::Aces => aces_fitted(c),
+            ToneMapOperator::AgX => agx(c),
+        };
+        return saturate(mapped);
+    }
+}
+
+
+fn saturate(c: Color) -> Color {
+    Color::new(c.x.clamp(0.0, 1.0), c.y.clamp(0.0, 1.0), c.z.clamp(0.0, 1.0))
+}
+
+fn mat_mul(m: &[[f64; 3]; 3], c: Color) -> Color {
+    let row = |r: &[f64; 3]| vec3::dot(Color::new(r[0], r[1], r[2]), c);
+    return Color::new(row(&m[0]), row(&m[1]), row(&m[2]));
+}
+
+fn reinhard_extended(c: Color, white_point: f64) -> Color {
+    let l_in = luminance(c);
+    if l_in <= 0.0 {
+        return Color::from_float(0.0);
+    }
+    let l_out = l_in * (1.0 + l_in / (white_point * white_point)) / (1.0 + l_in);
+    return c * (l_out / l_in);
+}
+
+fn aces_fitted(c: Color) -> Color {
+    // sRGB to the ACES fitting space (RRT_SAT), and back out after the curve (ODT_SAT)
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let rrt_and_odt_fit = |x: f64| {
+        let a = x * (x + 0.0245786) - 0.000090537;
+        let b = x * (0.983729 * x + 0.4329510) + 0.238081;
+        a / b
+    };
+
+    let v = mat_mul(&INPUT, c);
+    let v = Color::new(rrt_and_odt_fit(v.x), rrt_and_odt_fit(v.y), rrt_and_odt_fit(v.z));
+    return mat_mul(&OUTPUT, v);
+}
+
+fn agx(c: Color) -> Color {
+    // Inset into the AgX working space, encode as log2 exposure, apply the sigmoid contrast
+    // curve, then outset again. The curve produces display encoded values, which are decoded
+    // back to linear with a 2.2 power
+    const INSET: [[f64; 3]; 3] = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104],
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+    ];
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    let contrast = |x: f64| {
+        let encoded = ((f64::max(x, 1e-10).log2() - MIN_EV) / (MAX_EV - MIN_EV)).clamp(0.0, 1.0);
+        let x2 = encoded * encoded;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * encoded + 31.96 * x4 - 6.868 * x2 * encoded
+            + 0.4298 * x2 + 0.1191 * encoded - 0.00232
+    };
+
+    let v = mat_mul(&INSET, c);
+    let v = Color::new(contrast(v.x), contrast(v.y), contrast(v.z));
+    let v = mat_mul(&OUTSET, v);
+    let decode = |x: f64| f64::powf(f64::max(x, 0.0), 2.2);
+    return Color::new(decode(v.x), decode(v.y), decode(v.z));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::colors::{linear_to_srgb, srgb_to_linear};
+
+    const ALL: [ToneMapOperator; 5] = [
+        ToneMapOperator::Clamp,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::ReinhardExtended { white_point: 4.0 },
+        ToneMapOperator::Aces,
+        ToneMapOperator::AgX,
+    ];
+
+    fn grey(operator: ToneMapOperator, exposure: f64, x: f64) -> Color {
+        return ToneMapping::new(exposure, operator).apply(Color::from_float(x));
+    }
+
+    #[test]
+    fn srgb_encodes_black_and_white_exactly() {
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-12);
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-12);
+
+        // The two pieces of the curve meet, and decoding undoes encoding
+        assert!((linear_to_srgb(0.0031308) - linear_to_srgb(0.0031309)).abs() < 1e-5);
+        for x in [0.001, 0.0031308, 0.01, 0.18, 0.5, 0.9] {
+            assert!((srgb_to_linear(linear_to_srgb(x)) - x).abs() < 1e-12, "{}", x);
+        }
+    }
+
+    #[test]
+    fn reinhard_maps_known_values() {
+        assert!((grey(ToneMapOperator::Reinhard, 0.0, 1.0).x - 0.5).abs() < 1e-12);
+        assert!((grey(ToneMapOperator::Reinhard, 0.0, 3.0).x - 0.75).abs() < 1e-12);
+
+        // The extended curve reaches exactly white at the white point, and clips above it
+        let extended = ToneMapOperator::ReinhardExtended { white_point: 4.0 };
+        assert!((grey(extended, 0.0, 4.0).x - 1.0).abs() < 1e-12);
+        assert_eq!(grey(extended, 0.0, 10.0).x, 1.0);
+        assert_eq!(grey(extended, 0.0, 0.0).x, 0.0);
+    }
+
+    #[test]
+    fn clamp_cuts_off_outside_the_display_range() {
+        let mapped = ToneMapping::default().apply(Color::new(-1.0, 0.25, 2.0));
+        assert_eq!((mapped.x, mapped.y, mapped.z), (0.0, 0.25, 1.0));
+    }
+
+    #[test]
+    fn exposure_scales_radiance_in_stops() {
+        for operator in ALL {
+            for x in [0.05, 0.3, 2.0] {
+                let brighter = grey(operator, 1.0, x);
+                let doubled = grey(operator, 0.0, 2.0 * x);
+                assert!((brighter - doubled).length() < 1e-12, "{:?} at {}", operator, x);
+            }
+        }
+        assert!((grey(ToneMapOperator::Clamp, -2.0, 1.0).x - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn curves_are_monotonic_and_stay_in_range() {
+        // A grey ramp over many stops, from deep shadow to far beyond white
+        let ramp: Vec<f64> = (-60..=50).map(|i| f64::powf(2.0, i as f64 / 4.0)).collect();
+        for operator in ALL {
+            let mapped: Vec<Color> = ramp.iter().map(|&x| grey(operator, 0.0, x)).collect();
+            for c in &mapped {
+                for x in [c.x, c.y, c.z] {
+                    assert!((0.0..=1.0).contains(&x), "{:?}: {}", operator, x);
+                }
+            }
+            assert!(mapped.windows(2).all(|w| w[1].y >= w[0].y), "{:?} is not monotonic", operator);
+        }
+
+        // The filmic curves keep black black and approach white without passing it
+        for operator in [ToneMapOperator::Aces, ToneMapOperator::AgX] {
+            assert!(grey(operator, 0.0, 0.0).y < 1e-3, "{:?}", operator);
+            assert!(grey(operator, 0.0, 1e4).y > 0.97, "{:?}", operator);
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+use crate::utility::aabb::Aabb;
+use crate::utility::colors::Color;
+use crate::utility::common;
+use crate::utility::hittable::{HitRecord, Hittable};
+use crate::utility::interval::Interval;
+use crate::utility::linear_bvh::LinearBvh;
+use crate::utility::material::Material;
+use crate::utility::ray::Ray;
+use crate::utility::vec3::{self, Point3, Vec3};
+
+
+fn intersect(p0: Point3, p1: Point3, p2: Point3, r: &Ray, ray_t: Interval) -> Option<(f64, f64, f64)> {
+    // Möller–Trumbore ray/triangle intersection. Returns the ray parameter t and the
+    // barycentric weights (b1, b2) of p1 and p2 at the hit point
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let pvec = vec3::cross(r.direction(), edge2);
+    let det = vec3::dot(edge1, pvec);
+
+    // The ray is parallel to the triangle plane
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = r.origin() - p0;
+    let b1 = vec3::dot(tvec, pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let qvec = vec3::cross(tvec, edge1);
+    let b2 = vec3::dot(r.direction(), qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = vec3::dot(edge2, qvec) * inv_det;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+
+    return Some((t, b1, b2));
+}
+
+fn triangle_bbox(p0: Point3, p1: Point3, p2: Point3) -> Aabb {
+    let bbox = Aabb::surrounding(&Aabb::from_points(p0, p1), &Aabb::from_points(p2, p2));
+    return bbox.pad_to_minimums();
+}
+
+
+fn triangle_pdf_value(p0: Point3, p1: Point3, p2: Point3, origin: Point3, direction: Vec3) -> f64 {
+    // Solid angle density of picking direction from origin, for points spread uniformly over
+    // the triangle
+    let Some((t, _, _)) = intersect(p0, p1, p2, &Ray::new(origin, direction), Interval::new(0.001, common::INFINITY)) else {
+        return 0.0;
+    };
+
+    // Convert the uniform density over the area to one over solid angle
+    let normal = vec3::cross(p1 - p0, p2 - p0);
+    let area = 0.5 * normal.length();
+    let distance_squared = t * t * direction.length_squared();
+    let cosine = f64::abs(vec3::dot(direction, normal) / (direction.length() * normal.length()));
+
+    return distance_squared / (cosine * area);
+}
+
+fn random_triangle_point(p0: Point3, p1: Point3, p2: Point3) -> Point3 {
+    // Uniform point on the triangle, folding the unit square onto it through the square root
+    // of the first coordinate
+    let sqrt_r1 = common::random_double().sqrt();
+    let r2 = common::random_double();
+    return (1.0 - sqrt_r1) * p0 + (sqrt_r1 * (1.0 - r2)) * p1 + (sqrt_r1 * r2) * p2;
+}
+
+
+pub struct Triangle {
+    v0: Point3,
+    v1: Point3,
+    v2: Point3,
+    material: Arc<dyn Material>,
+    bbox: Aabb,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, material: Arc<dyn Material>) -> Self {
+        Triangle {
+            v0: v0,
+            v1: v1,
+            v2: v2,
+            material: material,
+            bbox: triangle_bbox(v0, v1, v2),
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (t, b1, b2) = intersect(self.v0, self.v1, self.v2, r, ray_t)?;
+
+        let mut rec = HitRecord {
+            t: t,
+            p: r.at(t),
+            mat: self.material.clone(),
+            normal: Default::default(),
+            u: b1,
+            v: b2,
+            barycentric: (b1, b2),
+            color: None,
+            object_id: 0,
+            front_face: Default::default(),
+        };
+
+        let outward_normal = vec3::unit_vector(vec3::cross(self.v1 - self.v0, self.v2 - self.v0));
+        rec.set_face_normal(r, outward_normal);
+        return Some(rec);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        return triangle_pdf_value(self.v0, self.v1, self.v2, origin, direction);
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        return random_triangle_point(self.v0, self.v1, self.v2) - origin;
+    }
+}
+
+
+#[derive(Debug)]
+pub enum MeshError {
+    AttributeCount { attribute: &'static str, count: usize, vertices: usize },
+    IndexOutOfRange { index: usize, vertices: usize },
+}
+
+impl Display for MeshError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            MeshError::AttributeCount { attribute, count, vertices } => {
+                write!(f, "mesh has {} {} for {} vertices, needs one per vertex or none", count, attribute, vertices)
+            }
+            MeshError::IndexOutOfRange { index, vertices } => {
+                write!(f, "triangle references vertex {} of {}", index, vertices)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+
+// Vertex data shared by every triangle of a mesh. Normals, uvs and colors are either empty or
+// hold one entry per position, and are indexed by the same vertex indices.
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Color>,
+    pub indices: Vec<[usize; 3]>,
+    pub material: Arc<dyn Material>,
+}
+
+
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    index: usize,
+}
+
+impl MeshTriangle {
+    fn vertices(&self) -> [usize; 3] {
+        return self.mesh.indices[self.index];
+    }
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let mesh = &self.mesh;
+        let [i0, i1, i2] = self.vertices();
+        let (p0, p1, p2) = (mesh.positions[i0], mesh.positions[i1], mesh.positions[i2]);
+
+        let (t, b1, b2) = intersect(p0, p1, p2, r, ray_t)?;
+        let b0 = 1.0 - b1 - b2;
+
+        let (u, v) = if mesh.uvs.is_empty() {
+            (b1, b2)
+        } else {
+            let (uv0, uv1, uv2) = (mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]);
+            (b0*uv0.0 + b1*uv1.0 + b2*uv2.0, b0*uv0.1 + b1*uv1.1 + b2*uv2.1)
+        };
+
+        let mut rec = HitRecord {
+            t: t,
+            p: r.at(t),
+            mat: mesh.material.clone(),
+            normal: Default::default(),
+            u: u,
+            v: v,
+            barycentric: (b1, b2),
+            color: if mesh.colors.is_empty() {
+                None
+            } else {
+                Some(b0*mesh.colors[i0] + b1*mesh.colors[i1] + b2*mesh.colors[i2])
+            },
+            object_id: 0,
+            front_face: Default::default(),
+        };
+
+        // Decide the facing from the geometric normal, then swap in the interpolated vertex
+        // normal for smooth shading
+        let outward_normal = vec3::unit_vector(vec3::cross(p1 - p0, p2 - p0));
+        rec.set_face_normal(r, outward_normal);
+
+        if !mesh.normals.is_empty() {
+            let n = b0*mesh.normals[i0] + b1*mesh.normals[i1] + b2*mesh.normals[i2];
+            if !n.near_zero() {
+                let shading_normal = vec3::unit_vector(n);
+                rec.normal = if rec.front_face { shading_normal } else { -shading_normal };
+            }
+        }
+        return Some(rec);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [i0, i1, i2] = self.vertices();
+        let positions = &self.mesh.positions;
+        return triangle_bbox(positions[i0], positions[i1], positions[i2]);
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let [i0, i1, i2] = self.vertices();
+        let positions = &self.mesh.positions;
+        return triangle_pdf_value(positions[i0], positions[i1], positions[i2], origin, direction);
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        let [i0, i1, i2] = self.vertices();
+        let positions = &self.mesh.positions;
+        return random_triangle_point(positions[i0], positions[i1], positions[i2]) - origin;
+    }
+}
+
+
+// Indexed triangle mesh. The triangles reference one shared set of vertex buffers and are
+// organised in their own bounding volume hierarchy.
+pub struct TriangleMesh {
+    mesh: Arc<MeshData>,
+    bvh: LinearBvh,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Point3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f64, f64)>,
+        indices: Vec<[usize; 3]>,
+        material: Arc<dyn Material>,
+    ) -> Result<Self, MeshError> {
+        Self::from_data(MeshData {
+            positions: positions,
+            normals: normals,
+            uvs: uvs,
+            colors: Vec::new(),
+            indices: indices,
+            material: material,
+        })
+    }
+
+    pub fn from_data(data: MeshData) -> Result<Self, MeshError> {
+        // Check that the attributes and indices fit the positions, so hits can index freely
+        let vertices = data.positions.len();
+        for (attribute, count) in [("normals", data.normals.len()), ("uvs", data.uvs.len()), ("colors", data.colors.len())] {
+            if count != 0 && count != vertices {
+                return Err(MeshError::AttributeCount { attribute: attribute, count: count, vertices: vertices });
+            }
+        }
+        if let Some(&index) = data.indices.iter().flatten().find(|&&i| i >= vertices) {
+            return Err(MeshError::IndexOutOfRange { index: index, vertices: vertices });
+        }
+
+        let mesh = Arc::new(data);
+
+        let triangles: Vec<Box<dyn Hittable>> = (0..mesh.indices.len())
+            .map(|index| Box::new(MeshTriangle { mesh: mesh.clone(), index: index }) as Box<dyn Hittable>)
+            .collect();
+
+        Ok(TriangleMesh {
+            bvh: LinearBvh::from_objects(triangles),
+            mesh: mesh,
+        })
+    }
+
+    pub fn data(&self) -> &MeshData {
+        return &self.mesh;
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        return self.mesh.indices.len();
+    }
+
+    pub fn triangles(&self) -> Vec<Box<dyn Hittable>> {
+        // Separate handles to each triangle, sharing the mesh data, such as for sampling the
+        // triangles of an emissive mesh as lights
+        return (0..self.mesh.indices.len())
+            .map(|index| Box::new(MeshTriangle { mesh: self.mesh.clone(), index: index }) as Box<dyn Hittable>)
+            .collect();
+    }
+
+    pub fn triangle_area(&self, index: usize) -> f64 {
+        let [i0, i1, i2] = self.mesh.indices[index];
+        let positions = &self.mesh.positions;
+        return 0.5 * vec3::cross(positions[i1] - positions[i0], positions[i2] - positions[i0]).length();
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        return self.bvh.hit(r, ray_t);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bvh.bounding_box();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::material::Lambertian;
+
+    fn material() -> Arc<dyn Material> {
+        return Arc::new(Lambertian::new(Color::from_float(0.5)));
+    }
+
+    fn down(x: f64, y: f64) -> Ray {
+        // Ray straight down onto the z = 0 plane
+        return Ray::new(Point3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        return (a - b).length() < 1e-9;
+    }
+
+    fn unit_triangle_mesh(normals: Vec<Vec3>) -> TriangleMesh {
+        let positions = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0)];
+        return TriangleMesh::new(positions, normals, Vec::new(), vec![[0, 1, 2]], material()).unwrap();
+    }
+
+    #[test]
+    fn triangle_hits_inside_and_misses_outside() {
+        let triangle = Triangle::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0), material());
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+
+        let rec = triangle.hit(&down(0.25, 0.5), ray_t).expect("ray through the triangle");
+        assert!((rec.t - 1.0).abs() < 1e-12);
+        assert!(close(rec.p, Point3::new(0.25, 0.5, 0.0)));
+        assert!(close(rec.normal, Vec3::new(0.0, 0.0, 1.0)) && rec.front_face);
+
+        // Past the hypotenuse, beside either leg, and parallel to the plane
+        assert!(triangle.hit(&down(0.6, 0.6), ray_t).is_none());
+        assert!(triangle.hit(&down(-0.1, 0.5), ray_t).is_none());
+        assert!(triangle.hit(&down(0.5, -0.1), ray_t).is_none());
+        assert!(triangle.hit(&Ray::new(Point3::new(-1.0, 0.2, 0.0), Vec3::new(1.0, 0.0, 0.0)), ray_t).is_none());
+        // Behind the ray origin
+        assert!(triangle.hit(&Ray::new(Point3::new(0.2, 0.2, -1.0), Vec3::new(0.0, 0.0, -1.0)), ray_t).is_none());
+    }
+
+    #[test]
+    fn barycentric_weights_locate_the_hit() {
+        let (p0, p1, p2) = (Point3::new(1.0, 0.0, 0.0), Point3::new(3.0, 0.0, 0.0), Point3::new(1.0, 4.0, 0.0));
+        let triangle = Triangle::new(p0, p1, p2, material());
+        let expected = (0.25, 0.5);
+        let point = (1.0 - expected.0 - expected.1) * p0 + expected.0 * p1 + expected.1 * p2;
+
+        let rec = triangle.hit(&down(point.x, point.y), Interval::new(0.001, f64::INFINITY)).unwrap();
+        assert!((rec.barycentric.0 - expected.0).abs() < 1e-12 && (rec.barycentric.1 - expected.1).abs() < 1e-12);
+        assert!((rec.u - expected.0).abs() < 1e-12 && (rec.v - expected.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mesh_interpolates_vertex_normals() {
+        let normals = vec![Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 1.0)];
+        let mesh = unit_triangle_mesh(normals.clone());
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+
+        // Halfway along the edge from vertex 0 to vertex 1, the normal is the normalized mean
+        let rec = mesh.hit(&down(0.5, 0.0001), ray_t).unwrap();
+        let expected = vec3::unit_vector(Vec3::new(0.5, 0.0001, 1.0));
+        assert!((rec.normal - expected).length() < 1e-6, "{:?}", rec.normal);
+
+        // At a vertex, the normal is that of the vertex, and flips when seen from behind
+        let rec = mesh.hit(&down(0.000_001, 0.999_998), ray_t).unwrap();
+        assert!((rec.normal - vec3::unit_vector(normals[2])).length() < 1e-5);
+        let rec = mesh.hit(&Ray::new(Point3::new(0.2, 0.2, -1.0), Vec3::new(0.0, 0.0, 1.0)), ray_t).unwrap();
+        assert!(!rec.front_face && rec.normal.z < 0.0);
+
+        // Without normals the face is flat
+        let rec = unit_triangle_mesh(Vec::new()).hit(&down(0.5, 0.0001), ray_t).unwrap();
+        assert!(close(rec.normal, Vec3::new(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn degenerate_triangles_are_never_hit() {
+        // Collinear vertices have no area, and no direction towards them has any density
+        let (p0, p1, p2) = (Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0), Point3::new(2.0, 2.0, 0.0));
+        let triangle = Triangle::new(p0, p1, p2, material());
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        assert!(triangle.hit(&down(1.0, 1.0), ray_t).is_none());
+        assert!(triangle.hit(&Ray::new(Point3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, -1.0)), ray_t).is_none());
+        assert_eq!(triangle.pdf_value(Point3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, -1.0)), 0.0);
+
+        let mesh = TriangleMesh::new(vec![p0, p1, p2], Vec::new(), Vec::new(), vec![[0, 1, 2]], material()).unwrap();
+        assert_eq!(mesh.triangle_area(0), 0.0);
+        assert!(mesh.hit(&down(1.0, 1.0), ray_t).is_none());
+    }
+
+    #[test]
+    fn mesh_rejects_mismatched_attributes_and_indices() {
+        let positions = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0)];
+        let result = TriangleMesh::new(positions.clone(), vec![Vec3::new(0.0, 0.0, 1.0)], Vec::new(), vec![[0, 1, 2]], material());
+        assert!(matches!(result, Err(MeshError::AttributeCount { attribute: "normals", count: 1, vertices: 3 })));
+
+        let result = TriangleMesh::new(positions, Vec::new(), Vec::new(), vec![[0, 1, 2], [2, 1, 3]], material());
+        assert!(matches!(result, Err(MeshError::IndexOutOfRange { index: 3, vertices: 3 })));
+    }
+}
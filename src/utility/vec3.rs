@@ -1,4 +1,4 @@
-use std::ops::{Add, AddAssign, Neg, Sub, Mul, MulAssign, Div, DivAssign};
+use std::ops::{Add, AddAssign, Neg, Sub, Mul, MulAssign, Div, DivAssign, Index};
 use std::fmt::{Display, Formatter, Result};
 use crate::utility::common;
 
@@ -80,6 +80,19 @@ impl Display for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, i: usize) -> &f64 {
+        match i {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", i),
+        }
+    }
+}
+
 impl Neg for Vec3 {
     type Output = Self;
 
@@ -210,3 +223,31 @@ pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: f64) -> Vec3 {
     let r_out_parallel: Vec3 = -f64::sqrt(f64::abs(1.0 - r_out_perpendicular.length_squared())) * n;
     return r_out_perpendicular + r_out_parallel;
 }
+
+pub fn random_cosine_direction() -> Vec3 {
+    // Random direction on the hemisphere around +z, with density proportional to the cosine
+    // of its angle from z
+    let r1 = common::random_double();
+    let r2 = common::random_double();
+
+    let phi = 2.0 * common::PI * r1;
+    let x = f64::cos(phi) * f64::sqrt(r2);
+    let y = f64::sin(phi) * f64::sqrt(r2);
+    let z = f64::sqrt(1.0 - r2);
+
+    return Vec3::new(x, y, z);
+}
+
+pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+    // Random direction around +z towards a sphere of the given radius, uniform over the solid
+    // angle it covers from the given squared distance to its center
+    let r1 = common::random_double();
+    let r2 = common::random_double();
+    let z = 1.0 + r2 * (f64::sqrt(1.0 - radius * radius / distance_squared) - 1.0);
+
+    let phi = 2.0 * common::PI * r1;
+    let x = f64::cos(phi) * f64::sqrt(1.0 - z * z);
+    let y = f64::sin(phi) * f64::sqrt(1.0 - z * z);
+
+    return Vec3::new(x, y, z);
+}